@@ -1,3 +1,4 @@
+use std::collections::HashMap;
 use std::default::Default;
 use dittolive_ditto::prelude::DocumentId;
 
@@ -11,8 +12,32 @@ pub const PEER_COLLECTION_NAME: &str = "cmesh-peers";
 pub const PEER_LOG_SIZE: u32 = 16;
 pub const GRAPH_SPANNING_MAX_DEGREE: usize = 3;
 pub const GRAPH_LA_CLIQUE_SIZE: usize = 4;
+pub const GRAPH_RANDOM_AVG_DEGREE: f64 = 4.0;
+pub const GRAPH_BA_ATTACH_EDGES: usize = 2;
+pub const GRAPH_WS_LATTICE_DEGREE: usize = 4;
+pub const GRAPH_WS_REWIRE_PROBABILITY: f64 = 0.1;
+pub const GRAPH_GEO_AREA_SIZE: f64 = 1000.0;
+pub const GRAPH_GEO_RADIO_RANGE: f64 = 250.0;
+pub const GRAPH_GEO_MAX_PLACEMENTS: usize = 100;
+pub const GRAPH_ISLANDS_CLUSTERS: usize = 3;
+pub const GRAPH_ISLANDS_BRIDGES: usize = 1;
+pub const GRAPH_K_CONNECTIVITY: usize = 3;
+pub const GRAPH_TIER_FANOUT: [usize; 2] = [5, 4];
+pub const GRAPH_GATEWAYS: usize = 3;
+pub const GRAPH_GATEWAY_UPLINKS: usize = 2;
+pub const MOBILITY_MIN_SPEED: f64 = 1.0;
+pub const MOBILITY_MAX_SPEED: f64 = 10.0;
+pub const MOBILITY_PAUSE_MSEC: u64 = 5000;
+pub const MOBILITY_TICK_MSEC: u64 = 5000;
+pub const CHURN_MEAN_DOWNTIME_SEC: f64 = 10.0;
+pub const FLAP_UP_SEC: f64 = 20.0;
+pub const FLAP_DOWN_SEC: f64 = 5.0;
+pub const GRAPH_MULES: usize = 1;
+pub const MULE_DWELL_SEC: f64 = 20.0;
+pub const MULE_TRANSIT_SEC: f64 = 10.0;
 pub const QUERY_POLL_SEC: u64 = 2;  // peer delay between polling for coord. info
 pub const REPORT_PROPAGATION_SEC: u64 = 2;  // peer wait before shutting down
+pub const REPORT_WAIT_SEC: u64 = 10;  // coordinator wait for peer reports after the test
 pub const HEARTBEAT_SEC: u64 = 2; // peer delay between heartbeat writes
 
 
@@ -40,6 +65,48 @@ impl Default for ExecutionPlan {
             max_msg_delay_msec: 1100,
             peers: Vec::new(),
             connections: PeerGraph::new(),
+            graph_params: GraphParams::default(),
+            seed: rand::random(),
+            epochs: Vec::new(),
+            partition: None,
+            churn: HashMap::new(),
+            link_flaps: Vec::new(),
+            mules: None,
+        }
+    }
+}
+
+impl Default for GraphParams {
+    fn default() -> Self {
+        GraphParams {
+            graph_type: GraphType::Complete,
+            edge_probability: None,
+            attach_edges: None,
+            lattice_degree: None,
+            rewire_probability: None,
+            grid_rows: None,
+            grid_cols: None,
+            area_size: None,
+            radio_range: None,
+            star_hub: None,
+            clusters: None,
+            bridges_per_cluster: None,
+            cluster_graph: None,
+            graph_file: None,
+            connectivity: None,
+            tier_fanout: None,
+            gateways: None,
+            gateway_uplinks: None,
+            min_speed: None,
+            max_speed: None,
+            pause_msec: None,
+            mobility_tick_msec: None,
+            trace_start_sec: None,
+            mules: None,
+            mule_dwell_sec: None,
+            mule_transit_sec: None,
+            max_degree: None,
+            orientation: EdgeOrientation::AsGenerated,
         }
     }
 }
@@ -49,6 +116,7 @@ impl Default for PeerRecord {
         PeerRecord {
             timestamp: util::system_time_msec(),
             data: String::new(),
+            seq: 0,
         }
     }
 }
@@ -1,9 +1,19 @@
-use rand::prelude::IteratorRandom;
+use rand::prelude::SliceRandom;
 use rand::Rng;
-use std::collections::{HashSet, VecDeque};
+use std::collections::{BTreeSet, HashMap, HashSet, VecDeque};
 
+use crate::default::{GRAPH_GEO_MAX_PLACEMENTS, GRAPH_LA_CLIQUE_SIZE};
 use crate::types::*;
 
+pub mod analytics;
+pub mod export;
+pub mod import;
+#[cfg(test)]
+pub mod test_util;
+pub mod trace;
+
+use analytics::connected_components;
+
 // Connection graph generation. We represent the graphs as directed to indicate which side of a
 // connection is the actie side: An edge from u to v,  u -> v, indicates that u calls connect(),
 // whereas v does listen().
@@ -40,11 +50,14 @@ pub fn complete_graph(_peers: &[PeerId]) -> PeerGraph {
     peers.sort_by(|a, b| b.cmp(a));
     for v in peers {
         let mut edges_from_v = HashSet::new();
-        for (u, _) in graph.nmap.iter_mut() {
+        for u in graph.nmap.keys() {
             // add edges from to all vertices in G
             edges_from_v.insert(u.clone());
         }
-        graph.nmap.insert(v.to_string(), edges_from_v);
+        graph.add_vertex(&v);
+        for u in edges_from_v {
+            graph.add_edge(&v, &u);
+        }
     }
     graph
 }
@@ -75,7 +88,10 @@ pub fn spanning_tree(mut _peers: &Vec<PeerId>, max_degree: usize) -> PeerGraph {
                 }
             }
         }
-        graph.nmap.insert(p.id, p.neighbors);
+        graph.add_vertex(&p.id);
+        for v in &p.neighbors {
+            graph.add_edge(&p.id, v);
+        }
     }
     graph
 }
@@ -85,30 +101,27 @@ pub fn spanning_tree(mut _peers: &Vec<PeerId>, max_degree: usize) -> PeerGraph {
 // - Generally avoid singly-connected nodes (degree > 1) (TODO tweak attach probability?)
 // Inspired by "Local preferential attachment model for hierarchical newtorks", Wang et al. 2009
 // with alpha = 1.
-pub fn local_attachment_model(peers: &[PeerId], m: usize) -> PeerGraph {
-    let mut rng = &mut rand::thread_rng();
+pub fn local_attachment_model<R: Rng>(rng: &mut R, peers: &[PeerId], m: usize) -> PeerGraph {
     assert!(m <= peers.len());
     // Start with a clique (complete graph) of m nodes
     let mut graph = complete_graph(&peers[..m]);
-    let to_add = Vec::from(&peers[m..]);
-    for v in to_add {
+    for (i, v) in peers[m..].iter().enumerate() {
         // Add v to graph, but first choose edges
         let mut v_edges = HashSet::new();
 
-        // Chose root "LAN" attachment node randomly
-        let root: &PeerId = graph.nmap.keys().choose(&mut rng).as_ref().unwrap();
+        // Chose root "LAN" attachment node randomly. The graph holds exactly the peers
+        // before v, so pick from that slice rather than the (unordered) vertex map.
+        let root: &PeerId = peers[..m + i].choose(rng).unwrap();
         // The LAN set is neighbors of root and the root
-        let mut lan = graph.undirected_links(root).unwrap();
-        lan.insert(root.to_string());
+        let mut lan: Vec<&PeerId> = graph.links(root).unwrap().iter().collect();
+        lan.push(root);
+        lan.sort();
         // Attach to nodes in LAN with degree-preferrential probability
-        let _sum = lan
-            .iter()
-            .map(|x| graph.undirected_links(x).as_ref().unwrap().len() as u64)
-            .sum::<u64>();
+        let _sum = lan.iter().map(|x| graph.degree(x) as u64).sum::<u64>();
         assert!(_sum>0);
         let sum_lan_degree = _sum as f64;
         for w in lan {
-            let w_degree = f64::from(graph.undirected_links(&w).as_ref().unwrap().len() as u32);
+            let w_degree = f64::from(graph.degree(w) as u32);
             let probability = w_degree / sum_lan_degree;
             let flip = rng.gen_range(0.0..1.0);
             if flip <= probability {
@@ -122,15 +135,700 @@ pub fn local_attachment_model(peers: &[PeerId], m: usize) -> PeerGraph {
         }
 
         // Add v to graph
-        graph.nmap.insert(v, v_edges);
+        graph.add_vertex(v);
+        for w in v_edges {
+            graph.add_edge(v, &w);
+        }
+    }
+    graph
+}
+
+// Erdős–Rényi G(n, p) random graph: each pair of peers is connected with probability p.
+// For small p the result is usually disconnected, so we then join the connected components with
+// a random edge between each component and the ones before it.
+pub fn random_graph<R: Rng>(rng: &mut R, peers: &[PeerId], p: f64) -> PeerGraph {
+    assert!((0.0..=1.0).contains(&p));
+    let mut graph = PeerGraph::new();
+    for (i, u) in peers.iter().enumerate() {
+        graph.add_vertex(u);
+        for v in &peers[i + 1..] {
+            if rng.gen_bool(p) {
+                graph.add_edge(u, v);
+            }
+        }
+    }
+    connect_components(rng, &mut graph);
+    graph
+}
+
+// Barabási–Albert preferential attachment model. Start with a clique of m + 1 peers, then add
+// each remaining peer with edges to m distinct existing peers, chosen with probability
+// proportional to their degree. Unlike local_attachment_model, attachment is global, so early
+// peers grow into hubs and the degree distribution follows a power law.
+pub fn barabasi_albert<R: Rng>(rng: &mut R, peers: &[PeerId], m: usize) -> PeerGraph {
+    assert!(m >= 1);
+    let seed_size = peers.len().min(m + 1);
+    let mut graph = complete_graph(&peers[..seed_size]);
+    // Each vertex appears here once per incident edge, so a uniform choice from this list is a
+    // degree-weighted choice of vertex.
+    let mut endpoints: Vec<PeerId> = Vec::new();
+    for (u, neighbors) in &graph.nmap {
+        for v in neighbors {
+            endpoints.push(u.clone());
+            endpoints.push(v.clone());
+        }
+    }
+    endpoints.sort();
+    for v in &peers[seed_size..] {
+        let mut targets = HashSet::new();
+        while targets.len() < m {
+            targets.insert(endpoints.choose(rng).unwrap().clone());
+        }
+        let mut targets: Vec<PeerId> = targets.into_iter().collect();
+        targets.sort();
+        for w in targets {
+            graph.add_edge(v, &w);
+            endpoints.push(v.clone());
+            endpoints.push(w);
+        }
+    }
+    graph
+}
+
+// Watts–Strogatz small-world model. Start with a ring lattice where each peer connects to its k/2
+// nearest neighbors on either side, then rewire each lattice edge with probability beta to a
+// uniformly chosen peer. Small beta keeps the lattice's local clustering while adding a few
+// long-range shortcuts. Rewiring can split off components, which we join afterwards.
+pub fn small_world<R: Rng>(rng: &mut R, peers: &[PeerId], k: usize, beta: f64) -> PeerGraph {
+    assert!((0.0..=1.0).contains(&beta));
+    let n = peers.len();
+    if k >= n.saturating_sub(1) {
+        return complete_graph(peers);
+    }
+    let mut graph = PeerGraph::new();
+    for u in peers {
+        graph.add_vertex(u);
+    }
+    for (i, u) in peers.iter().enumerate() {
+        for j in 1..=k / 2 {
+            graph.add_edge(u, &peers[(i + j) % n]);
+        }
+    }
+    for (i, u) in peers.iter().enumerate() {
+        for j in 1..=k / 2 {
+            let v = &peers[(i + j) % n];
+            if !rng.gen_bool(beta) {
+                continue;
+            }
+            let candidates: Vec<&PeerId> = peers
+                .iter()
+                .filter(|w| *w != u && !graph.has_edge(u, w))
+                .collect();
+            if let Some(w) = candidates.choose(rng) {
+                graph.remove_edge(u, v);
+                graph.add_edge(u, w);
+            }
+        }
+    }
+    connect_components(rng, &mut graph);
+    graph
+}
+
+// Path through peers in the given order: each peer connects to the next one.
+pub fn line_graph(peers: &[PeerId]) -> PeerGraph {
+    let mut graph = PeerGraph::new();
+    for u in peers {
+        graph.add_vertex(u);
+    }
+    for pair in peers.windows(2) {
+        graph.add_edge(&pair[0], &pair[1]);
+    }
+    graph
+}
+
+// Line graph with the last peer connected back to the first.
+pub fn ring_graph(peers: &[PeerId]) -> PeerGraph {
+    let mut graph = line_graph(peers);
+    if peers.len() > 2 {
+        graph.add_edge(peers.last().unwrap(), &peers[0]);
+    }
+    graph
+}
+
+// Every other peer connects to the hub, which only listens.
+pub fn star_graph(peers: &[PeerId], hub: &PeerId) -> PeerGraph {
+    let mut graph = PeerGraph::new();
+    graph.add_vertex(hub);
+    for u in peers.iter().filter(|u| *u != hub) {
+        graph.add_edge(u, hub);
+    }
+    graph
+}
+
+// Harary graph H(k, n): the fewest edges of any k-vertex-connected graph on n peers, so it stays
+// connected after removing any k - 1 peers. Each peer connects to its k/2 nearest neighbors on
+// either side of a ring, and for odd k also to the peer diametrically opposite. Every peer has
+// degree k, except that one peer has degree k + 1 when both k and n are odd. Falls back to a
+// complete graph when k >= n - 1, and is just a line for k = 1.
+pub fn harary_graph(peers: &[PeerId], k: usize) -> PeerGraph {
+    assert!(k >= 1);
+    let n = peers.len();
+    if k >= n.saturating_sub(1) {
+        return complete_graph(peers);
+    }
+    if k == 1 {
+        return line_graph(peers);
+    }
+    let mut graph = PeerGraph::new();
+    for u in peers {
+        graph.add_vertex(u);
+    }
+    for (i, u) in peers.iter().enumerate() {
+        for j in 1..=k / 2 {
+            graph.add_edge(u, &peers[(i + j) % n]);
+        }
+    }
+    if k % 2 == 1 {
+        // For even n this pairs off every peer. For odd n, peers 0..=n/2 each link to the peer
+        // (n + 1) / 2 ahead, so peer 0 also picks up the link from peer n / 2.
+        let half = (n + 1) / 2;
+        for i in 0..=(n - 1) / 2 {
+            graph.add_edge(&peers[i], &peers[(i + half) % n]);
+        }
+    }
+    graph
+}
+
+// Choose grid dimensions for n peers. Missing dimensions are derived from the given one, or
+// picked to make the grid as square as possible. If rows * cols is too small for n, rows are
+// added so that every peer fits; the last row may be partially filled.
+pub fn grid_dimensions(n: usize, rows: Option<usize>, cols: Option<usize>) -> (usize, usize) {
+    let n = n.max(1);
+    let cols = match (rows, cols) {
+        (_, Some(c)) => c.max(1),
+        (Some(r), None) => (n + r.max(1) - 1) / r.max(1),
+        (None, None) => (n as f64).sqrt().ceil() as usize,
+    };
+    let min_rows = (n + cols - 1) / cols;
+    (rows.unwrap_or(min_rows).max(min_rows), cols)
+}
+
+// Vertex index at (row, col) of a row-major grid of n vertices, if that cell is occupied
+fn grid_index(n: usize, cols: usize, row: usize, col: usize) -> Option<usize> {
+    let i = row * cols + col;
+    if col < cols && i < n {
+        Some(i)
+    } else {
+        None
+    }
+}
+
+// 2D lattice: peers fill a rows x cols grid in row-major order and connect to their right and
+// lower neighbors. Leftover peers form a partial last row. A torus additionally wraps each row
+// and column around, using the last occupied cell in a short row or column.
+pub fn lattice(peers: &[PeerId], rows: usize, cols: usize, wrap: bool) -> PeerGraph {
+    let n = peers.len();
+    let mut graph = PeerGraph::new();
+    let add = |graph: &mut PeerGraph, i: usize, j: usize| {
+        if i != j && !graph.has_edge(&peers[i], &peers[j]) {
+            graph.add_edge(&peers[i], &peers[j]);
+        }
+    };
+    for u in peers {
+        graph.add_vertex(u);
+    }
+    for row in 0..rows {
+        for col in 0..cols {
+            let i = match grid_index(n, cols, row, col) {
+                Some(i) => i,
+                None => continue,
+            };
+            if let Some(j) = grid_index(n, cols, row, col + 1) {
+                add(&mut graph, i, j);
+            }
+            if let Some(j) = grid_index(n, cols, row + 1, col) {
+                add(&mut graph, i, j);
+            }
+        }
+    }
+    if wrap {
+        for row in 0..rows {
+            let last = (0..cols).rev().find_map(|c| grid_index(n, cols, row, c));
+            if let (Some(first), Some(last)) = (grid_index(n, cols, row, 0), last) {
+                add(&mut graph, last, first);
+            }
+        }
+        for col in 0..cols {
+            let last = (0..rows).rev().find_map(|r| grid_index(n, cols, r, col));
+            if let (Some(first), Some(last)) = (grid_index(n, cols, 0, col), last) {
+                add(&mut graph, last, first);
+            }
+        }
+    }
+    graph
+}
+
+// Drawing positions for a lattice(), one unit apart
+pub fn lattice_positions(peers: &[PeerId], cols: usize) -> HashMap<PeerId, Position> {
+    peers
+        .iter()
+        .enumerate()
+        .map(|(i, p)| {
+            let pos = Position {
+                x: (i % cols) as f64,
+                y: (i / cols) as f64,
+            };
+            (p.clone(), pos)
+        })
+        .collect()
+}
+
+// Connect every pair of peers closer than range to each other.
+pub fn proximity_graph(
+    peers: &[PeerId],
+    positions: &HashMap<PeerId, Position>,
+    range: f64,
+) -> PeerGraph {
+    let mut graph = PeerGraph::new();
+    for (i, u) in peers.iter().enumerate() {
+        graph.add_vertex(u);
+        for v in &peers[i + 1..] {
+            if positions[u].distance(&positions[v]) <= range {
+                graph.add_edge(u, v);
+            }
+        }
+    }
+    graph
+}
+
+pub(crate) fn random_position<R: Rng>(rng: &mut R, area_size: f64) -> Position {
+    Position {
+        x: rng.gen_range(0.0..=area_size),
+        y: rng.gen_range(0.0..=area_size),
+    }
+}
+
+// Random geometric graph: place peers uniformly in an area_size x area_size square and connect
+// those within radio range of each other. We retry the placement a few times hoping for a
+// connected graph; if that fails, we repair the last placement by moving each peer outside the
+// largest component to a random spot within range of a peer that is already connected.
+// Returns the graph along with the peer positions.
+pub fn geometric_graph<R: Rng>(
+    rng: &mut R,
+    peers: &[PeerId],
+    area_size: f64,
+    range: f64,
+) -> (PeerGraph, HashMap<PeerId, Position>) {
+    assert!(area_size > 0.0 && range > 0.0);
+    let mut positions = HashMap::new();
+    for _ in 0..GRAPH_GEO_MAX_PLACEMENTS {
+        positions = peers
+            .iter()
+            .map(|p| (p.clone(), random_position(rng, area_size)))
+            .collect();
+        let graph = proximity_graph(peers, &positions, range);
+        if connected_components(&graph).len() <= 1 {
+            return (graph, positions);
+        }
+    }
+
+    let graph = proximity_graph(peers, &positions, range);
+    let mut comps = connected_components(&graph);
+    comps.sort_by_key(|c| std::cmp::Reverse(c.len()));
+    let mut connected = comps.remove(0);
+    for v in comps.into_iter().flatten() {
+        let anchor = positions[connected.choose(rng).unwrap()];
+        // Random point within 90% of range from anchor. Clamping to the area can only move
+        // it closer to anchor, since anchor is inside the area.
+        let angle = rng.gen_range(0.0..std::f64::consts::TAU);
+        let dist = rng.gen_range(0.0..=0.9 * range);
+        let pos = Position {
+            x: (anchor.x + dist * angle.cos()).clamp(0.0, area_size),
+            y: (anchor.y + dist * angle.sin()).clamp(0.0, area_size),
+        };
+        positions.insert(v.clone(), pos);
+        connected.push(v);
+    }
+    (proximity_graph(peers, &positions, range), positions)
+}
+
+// A graph whose peers are split into clusters, some of which act as bridges between clusters.
+pub struct ClusteredGraph {
+    pub graph: PeerGraph,
+    pub clusters: Vec<Vec<PeerId>>,
+    pub bridges: Vec<PeerId>,
+}
+
+// Split peers into k contiguous clusters whose sizes differ by at most one.
+pub fn split_clusters(peers: &[PeerId], k: usize) -> Vec<Vec<PeerId>> {
+    let k = k.clamp(1, peers.len().max(1));
+    let (size, extra) = (peers.len() / k, peers.len() % k);
+    let mut clusters = Vec::new();
+    let mut start = 0;
+    for i in 0..k {
+        let end = start + size + usize::from(i < extra);
+        clusters.push(peers[start..end].to_vec());
+        start = end;
+    }
+    clusters
+}
+
+// Each cluster connected internally as a complete graph or with local_attachment_model, and not
+// to any other cluster
+pub fn cluster_graphs<R: Rng>(
+    rng: &mut R,
+    clusters: &[Vec<PeerId>],
+    intra: GraphType,
+) -> PeerGraph {
+    assert!(intra == GraphType::Complete || intra == GraphType::LAModel);
+    let mut graph = PeerGraph::new();
+    for cluster in clusters {
+        let inner = if intra == GraphType::LAModel && cluster.len() >= GRAPH_LA_CLIQUE_SIZE {
+            local_attachment_model(rng, cluster, GRAPH_LA_CLIQUE_SIZE)
+        } else {
+            complete_graph(cluster)
+        };
+        graph.merge(inner);
     }
     graph
 }
 
+// "Islands" of densely connected peers, joined only through bridge peers. Peers are split into k
+// clusters, each connected internally as a complete graph or with local_attachment_model. The
+// first `bridges` peers of each cluster are its bridges: the j-th bridges of all clusters form a
+// clique, so any traffic between clusters has to pass through them.
+pub fn islands_graph<R: Rng>(
+    rng: &mut R,
+    peers: &[PeerId],
+    k: usize,
+    bridges: usize,
+    intra: GraphType,
+) -> ClusteredGraph {
+    let clusters = split_clusters(peers, k);
+    assert!(bridges >= 1 || clusters.len() == 1);
+    let mut graph = cluster_graphs(rng, &clusters, intra);
+    let mut all_bridges = Vec::new();
+    for j in 0..bridges {
+        let jth: Vec<PeerId> = clusters.iter().filter_map(|c| c.get(j).cloned()).collect();
+        graph.merge(complete_graph(&jth));
+        all_bridges.extend(jth);
+    }
+    ClusteredGraph {
+        graph,
+        clusters,
+        bridges: all_bridges,
+    }
+}
+
+// Edge devices that only connect through gateways. The first `gateways` peers are gateways and
+// form a clique; every other peer dials `uplinks` of them, assigned round-robin so the load is
+// spread evenly. Returns the graph along with the gateways.
+pub fn gateway_graph(
+    peers: &[PeerId],
+    gateways: usize,
+    uplinks: usize,
+) -> (PeerGraph, Vec<PeerId>) {
+    assert!(gateways >= 1 && uplinks >= 1);
+    let g = gateways.min(peers.len());
+    let mut graph = complete_graph(&peers[..g]);
+    for (i, u) in peers[g..].iter().enumerate() {
+        for j in 0..uplinks.min(g) {
+            graph.add_edge(u, &peers[(i + j) % g]);
+        }
+    }
+    (graph, peers[..g].to_vec())
+}
+
+// A graph built as a hierarchy of groups. Teams are the groups at the bottom tier, and tiers maps
+// each peer to the highest tier of any group it belongs to.
+pub struct TieredGraph {
+    pub graph: PeerGraph,
+    pub teams: Vec<Vec<PeerId>>,
+    pub tiers: HashMap<PeerId, usize>,
+}
+
+// Tree of cliques. Peers are split into teams of about fanouts[0] peers, each fully connected.
+// The first peer of each team is its leader; the leaders are in turn split into groups of about
+// fanouts[1] and fully connected, and so on up the tiers, reusing the last fan-out until a single
+// group remains at the top.
+pub fn hierarchy_graph(peers: &[PeerId], fanouts: &[usize]) -> TieredGraph {
+    assert!(!fanouts.is_empty() && fanouts.iter().all(|f| *f >= 2));
+    let mut graph = PeerGraph::new();
+    let mut tiers = HashMap::new();
+    for u in peers {
+        graph.add_vertex(u);
+        tiers.insert(u.clone(), 0);
+    }
+    let mut teams = split_clusters(peers, 1);
+    let mut members = peers.to_vec();
+    let mut tier = 0;
+    while members.len() > 1 {
+        let fanout = fanouts[tier.min(fanouts.len() - 1)];
+        let groups = split_clusters(&members, (members.len() + fanout - 1) / fanout);
+        for group in &groups {
+            for (u, neighbors) in complete_graph(group).nmap {
+                for v in neighbors {
+                    graph.add_edge(&u, &v);
+                }
+                tiers.insert(u, tier);
+            }
+        }
+        members = groups.iter().map(|g| g[0].clone()).collect();
+        if tier == 0 {
+            teams = groups;
+        }
+        tier += 1;
+    }
+    TieredGraph {
+        graph,
+        teams,
+        tiers,
+    }
+}
+
+// Rewire or drop links until no peer has more than max_degree of them, keeping the graph
+// connected. An excess link u–w of an overloaded peer u is moved to w–x, where x is the nearest
+// peer to u with spare capacity that u can still reach without the link, so w stays connected to
+// u through x. If there is no such peer, u–w is dropped as long as it isn't the only path between
+// them. Returns the peers still over the limit, e.g. the hub of a star when max_degree is 1.
+pub fn enforce_max_degree(graph: &mut PeerGraph, max_degree: usize) -> Vec<PeerId> {
+    assert!(max_degree >= 1);
+    // Ordered copy of the links, so rewiring picks the same peers on every run
+    let mut adj: HashMap<PeerId, BTreeSet<PeerId>> = graph
+        .nmap
+        .keys()
+        .map(|u| {
+            let links = graph.links(u).unwrap().iter().filter(|v| *v != u);
+            (u.clone(), links.cloned().collect())
+        })
+        .collect();
+    let mut vertices: Vec<PeerId> = adj.keys().cloned().collect();
+    vertices.sort();
+    let mut over = Vec::new();
+    for u in &vertices {
+        while adj[u].len() > max_degree {
+            // Try the busiest neighbors first, so dropping a link relieves them too
+            let mut ws: Vec<PeerId> = adj[u].iter().cloned().collect();
+            ws.sort_by_key(|w| std::cmp::Reverse(adj[w].len()));
+            let mut change = None;
+            for w in ws {
+                match rewire_target(&adj, u, &w, max_degree) {
+                    (Some(x), _) => {
+                        change = Some((w, Some(x)));
+                        break;
+                    }
+                    (None, true) if change.is_none() => change = Some((w, None)),
+                    _ => {}
+                }
+            }
+            let (w, x) = match change {
+                Some(c) => c,
+                None => {
+                    over.push(u.clone());
+                    break;
+                }
+            };
+            let w_dials = graph.nmap[&w].contains(u);
+            graph.remove_edge(u, &w);
+            adj.get_mut(u).unwrap().remove(&w);
+            adj.get_mut(&w).unwrap().remove(u);
+            if let Some(x) = x {
+                // Keep the same side dialing
+                if w_dials {
+                    graph.add_edge(&w, &x);
+                } else {
+                    graph.add_edge(&x, &w);
+                }
+                adj.get_mut(&w).unwrap().insert(x.clone());
+                adj.get_mut(&x).unwrap().insert(w);
+            }
+        }
+    }
+    over
+}
+
+// Breadth-first search from u that ignores the link u–w. Returns the first peer found that could
+// take a link to w without exceeding max_degree, and whether w is reachable at all.
+fn rewire_target(
+    adj: &HashMap<PeerId, BTreeSet<PeerId>>,
+    u: &PeerId,
+    w: &PeerId,
+    max_degree: usize,
+) -> (Option<PeerId>, bool) {
+    let mut seen = HashSet::from([u]);
+    let mut queue = VecDeque::from([u]);
+    let mut reached_w = false;
+    while let Some(a) = queue.pop_front() {
+        for b in &adj[a] {
+            if (a == u && b == w) || !seen.insert(b) {
+                continue;
+            }
+            if b == w {
+                reached_w = true;
+            } else if adj[b].len() < max_degree && !adj[w].contains(b) {
+                return (Some(b.clone()), true);
+            }
+            queue.push_back(b);
+        }
+    }
+    (None, reached_w)
+}
+
+// Reassign which side of each link dials, leaving the links themselves unchanged.
+pub fn orient_edges<R: Rng>(rng: &mut R, graph: &mut PeerGraph, orientation: EdgeOrientation) {
+    // Each link once, as (lower id, higher id), in sorted order so the result is reproducible
+    let mut edges: Vec<(PeerId, PeerId)> = Vec::new();
+    for (u, neighbors) in &graph.nmap {
+        for v in neighbors.iter().filter(|v| *v != u) {
+            let (a, b) = if u < v { (u, v) } else { (v, u) };
+            edges.push((a.clone(), b.clone()));
+        }
+    }
+    edges.sort();
+    edges.dedup();
+    let mut dials: Vec<bool> = match orientation {
+        EdgeOrientation::AsGenerated => return,
+        EdgeOrientation::LowerIdDials | EdgeOrientation::Balanced => vec![true; edges.len()],
+        EdgeOrientation::Random => edges.iter().map(|_| rng.gen_bool(0.5)).collect(),
+        // The endpoint with fewer links dials, so hubs only listen
+        EdgeOrientation::HubListens => edges
+            .iter()
+            .map(|(a, b)| graph.degree(a) <= graph.degree(b))
+            .collect(),
+    };
+    if orientation == EdgeOrientation::Balanced {
+        balance_out_degrees(&edges, &mut dials);
+    }
+    graph.clear_edges();
+    for ((a, b), d) in edges.iter().zip(dials) {
+        if d {
+            graph.add_edge(a, b);
+        } else {
+            graph.add_edge(b, a);
+        }
+    }
+}
+
+// Orient the links of every epoch the same way, so a link that lasts across epochs keeps its
+// dialer instead of being torn down and redialed at each boundary, and per-link schedules such as
+// link flaps apply in every epoch. The orientation is chosen once, over all the epochs' links;
+// with AsGenerated, each link keeps the direction of the first epoch it appears in.
+pub fn orient_epochs<R: Rng>(
+    rng: &mut R,
+    epochs: &mut [TopologyEpoch],
+    orientation: EdgeOrientation,
+) {
+    let mut all = PeerGraph::new();
+    for e in epochs.iter() {
+        let mut nmap: Vec<(&PeerId, &HashSet<PeerId>)> = e.connections.nmap.iter().collect();
+        nmap.sort_by_key(|(u, _)| *u);
+        for (u, neighbors) in nmap {
+            all.add_vertex(u);
+            for v in neighbors {
+                if !all.has_edge(u, v) {
+                    all.add_edge(u, v);
+                }
+            }
+        }
+    }
+    orient_edges(rng, &mut all, orientation);
+    for e in epochs.iter_mut() {
+        let mut oriented = PeerGraph::new();
+        for u in e.connections.nmap.keys() {
+            oriented.add_vertex(u);
+        }
+        for (u, neighbors) in &all.nmap {
+            for v in neighbors {
+                if e.connections.has_edge(u, v) {
+                    oriented.add_edge(u, v);
+                }
+            }
+        }
+        e.connections = oriented;
+    }
+}
+
+// Minimize the largest out-degree, starting from any orientation. While some peer s can reach a
+// peer t by following dial directions, and s dials at least two more links than t, reverse that
+// path: s dials one fewer, t one more, and those in between are unchanged. Each reversal lowers the
+// sum of squared out-degrees, so this terminates, and when no such path is left the largest
+// out-degree is as small as it can be.
+fn balance_out_degrees(edges: &[(PeerId, PeerId)], dials: &mut [bool]) {
+    let mut incident: HashMap<&PeerId, Vec<usize>> = HashMap::new();
+    for (e, (a, b)) in edges.iter().enumerate() {
+        incident.entry(a).or_default().push(e);
+        incident.entry(b).or_default().push(e);
+    }
+    let dialer = |e: usize, dials: &[bool]| if dials[e] { &edges[e].0 } else { &edges[e].1 };
+    let listener = |e: usize, dials: &[bool]| if dials[e] { &edges[e].1 } else { &edges[e].0 };
+    let mut out: HashMap<&PeerId, usize> = incident.keys().map(|u| (*u, 0)).collect();
+    for e in 0..edges.len() {
+        *out.get_mut(dialer(e, dials)).unwrap() += 1;
+    }
+    let mut vertices: Vec<&PeerId> = incident.keys().copied().collect();
+    vertices.sort();
+    loop {
+        vertices.sort_by_key(|u| std::cmp::Reverse(out[*u]));
+        let path = vertices.iter().find_map(|s| {
+            // Breadth-first along dial directions, remembering the link each peer was reached by
+            let mut via: HashMap<&PeerId, usize> = HashMap::new();
+            let mut queue = VecDeque::from([*s]);
+            while let Some(u) = queue.pop_front() {
+                if out[u] + 2 <= out[*s] {
+                    let mut path = Vec::new();
+                    let mut v = u;
+                    while v != *s {
+                        path.push(via[v]);
+                        v = dialer(via[v], dials);
+                    }
+                    return Some(path);
+                }
+                for &e in &incident[u] {
+                    let v = listener(e, dials);
+                    if dialer(e, dials) == u && v != *s && !via.contains_key(v) {
+                        via.insert(v, e);
+                        queue.push_back(v);
+                    }
+                }
+            }
+            None
+        });
+        let path = match path {
+            Some(p) => p,
+            None => break,
+        };
+        *out.get_mut(dialer(*path.last().unwrap(), dials)).unwrap() -= 1;
+        *out.get_mut(listener(path[0], dials)).unwrap() += 1;
+        for e in path {
+            dials[e] = !dials[e];
+        }
+    }
+}
+
+// Join all components of graph into one by adding an edge from a random vertex in each
+// component to a random vertex in the components preceding it.
+fn connect_components<R: Rng>(rng: &mut R, graph: &mut PeerGraph) {
+    let comps = connected_components(graph);
+    let mut joined: Vec<PeerId> = Vec::new();
+    for comp in comps {
+        if !joined.is_empty() {
+            let u = comp.choose(rng).unwrap();
+            let v = joined.choose(rng).unwrap();
+            graph.add_edge(u, v);
+        }
+        joined.extend(comp);
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use std::{collections::HashSet, fs::File, io::Write};
 
+    use rand::rngs::StdRng;
+    use rand::{thread_rng, SeedableRng};
+
+    use super::analytics::{is_connected, is_k_connected, vertex_connectivity};
+    use super::test_util::to_peer_ids_vec;
     use super::*;
 
     #[test]
@@ -153,10 +851,6 @@ mod tests {
         HashSet::from_iter(to_peer_ids_vec(ids))
     }
 
-    fn to_peer_ids_vec(ids: std::ops::Range<usize>) -> Vec<PeerId> {
-        ids.into_iter().map(|x| x.to_string()).collect()
-    }
-
     #[test]
     pub fn test_spanning_tree() {
         let peers = to_peer_ids_vec(0..10);
@@ -179,25 +873,393 @@ mod tests {
         }
     }
 
+    #[test]
+    pub fn test_random_graph() {
+        let peers = to_peer_ids_vec(0..50);
+        // p = 0 gives no edges before repair, so we get a tree joining all singletons
+        let sparse = random_graph(&mut thread_rng(), &peers, 0.0);
+        assert_eq!(sparse.nmap.len(), 50);
+        assert!(is_connected(&sparse));
+        let num_edges: usize = sparse.nmap.values().map(|n| n.len()).sum();
+        assert_eq!(num_edges, 49);
+
+        let dense = random_graph(&mut thread_rng(), &peers, 1.0);
+        for u in &peers {
+            assert_eq!(dense.undirected_links(u).unwrap().len(), 49);
+        }
+
+        for _ in 0..10 {
+            assert!(is_connected(&random_graph(&mut thread_rng(), &peers, 0.05)));
+        }
+    }
+
+    #[test]
+    pub fn test_barabasi_albert() {
+        let peers = to_peer_ids_vec(0..100);
+        let graph = barabasi_albert(&mut thread_rng(), &peers, 2);
+        assert_eq!(graph.nmap.len(), 100);
+        assert!(is_connected(&graph));
+        // Seed clique of 3 has 3 edges, then 2 more for each of the other 97 peers
+        let num_edges: usize = graph.nmap.values().map(|n| n.len()).sum();
+        assert_eq!(num_edges, 3 + 97 * 2);
+        for u in &peers {
+            assert!(graph.undirected_links(u).unwrap().len() >= 2);
+        }
+
+        // Fewer peers than the seed clique: just a complete graph
+        let small = barabasi_albert(&mut thread_rng(), &to_peer_ids_vec(0..3), 4);
+        assert_eq!(small.nmap.values().map(|n| n.len()).sum::<usize>(), 3);
+    }
+
+    #[test]
+    pub fn test_small_world() {
+        let peers = to_peer_ids_vec(0..20);
+        // beta = 0: plain ring lattice
+        let lattice = small_world(&mut thread_rng(), &peers, 4, 0.0);
+        for (i, u) in peers.iter().enumerate() {
+            let links = lattice.undirected_links(u).unwrap();
+            assert_eq!(links.len(), 4);
+            assert!(links.contains(&peers[(i + 1) % 20]));
+            assert!(links.contains(&peers[(i + 2) % 20]));
+        }
+
+        // Rewiring keeps the edge count, and we stay connected
+        for beta in [0.1, 0.5, 1.0] {
+            let graph = small_world(&mut thread_rng(), &peers, 4, beta);
+            let num_edges: usize = graph.nmap.values().map(|n| n.len()).sum();
+            assert!(num_edges >= 40);
+            assert!(is_connected(&graph));
+        }
+
+        // k too large for n: complete graph
+        let small = small_world(&mut thread_rng(), &to_peer_ids_vec(0..4), 4, 0.5);
+        assert_eq!(small.nmap.values().map(|n| n.len()).sum::<usize>(), 6);
+    }
+
+    #[test]
+    pub fn test_primitive_graphs() {
+        let peers = to_peer_ids_vec(0..6);
+        let degree = |g: &PeerGraph, i: usize| g.undirected_links(&peers[i]).unwrap().len();
+
+        let line = line_graph(&peers);
+        assert!(is_connected(&line));
+        assert_eq!(degree(&line, 0), 1);
+        assert_eq!(degree(&line, 5), 1);
+        for i in 1..5 {
+            assert_eq!(degree(&line, i), 2);
+        }
+
+        let ring = ring_graph(&peers);
+        assert!(ring.has_edge(&peers[5], &peers[0]));
+        for i in 0..6 {
+            assert_eq!(degree(&ring, i), 2);
+        }
+        // Two peers: a single edge, not a double one
+        let pair = ring_graph(&peers[..2]);
+        assert_eq!(pair.nmap.values().map(|n| n.len()).sum::<usize>(), 1);
+
+        let star = star_graph(&peers, &peers[3]);
+        assert_eq!(star.nmap.len(), 6);
+        assert_eq!(degree(&star, 3), 5);
+        assert!(star.nmap[&peers[3]].is_empty());
+        for i in [0, 1, 2, 4, 5] {
+            assert_eq!(star.nmap[&peers[i]], HashSet::from([peers[3].clone()]));
+        }
+    }
+
+    #[test]
+    pub fn test_harary_graph() {
+        for n in [7, 8, 20] {
+            let peers = to_peer_ids_vec(0..n);
+            for k in 1..=5 {
+                let graph = harary_graph(&peers, k);
+                assert_eq!(vertex_connectivity(&graph), k);
+                let degrees: Vec<usize> = peers
+                    .iter()
+                    .map(|p| graph.undirected_links(p).unwrap().len())
+                    .collect();
+                // k = 1 is a line, whose inner peers have degree 2
+                let odd = k == 1 || (k % 2 == 1 && n % 2 == 1);
+                let max = if odd { k + 1 } else { k };
+                assert_eq!(*degrees.iter().min().unwrap(), k);
+                assert_eq!(*degrees.iter().max().unwrap(), max);
+            }
+        }
+        let small = harary_graph(&to_peer_ids_vec(0..4), 3);
+        assert!(is_k_connected(&small, 3));
+        assert!(!is_k_connected(&small, 4));
+    }
+
+    #[test]
+    pub fn test_islands_graph() {
+        let peers = to_peer_ids_vec(0..20);
+        let islands = islands_graph(&mut thread_rng(), &peers, 3, 2, GraphType::Complete);
+        assert!(is_connected(&islands.graph));
+        let sizes: Vec<usize> = islands.clusters.iter().map(|c| c.len()).collect();
+        assert_eq!(sizes, vec![7, 7, 6]);
+        assert_eq!(islands.bridges.len(), 6);
+
+        let cluster_of = |p: &PeerId| islands.clusters.iter().position(|c| c.contains(p));
+        for (u, neighbors) in &islands.graph.nmap {
+            for v in neighbors {
+                // Edges between clusters only join bridges
+                if cluster_of(u) != cluster_of(v) {
+                    assert!(islands.bridges.contains(u) && islands.bridges.contains(v));
+                }
+            }
+        }
+        // Inside each cluster: complete
+        for c in &islands.clusters {
+            for u in c {
+                for v in c {
+                    assert!(u == v || islands.graph.has_edge(u, v));
+                }
+            }
+        }
+
+        let la = islands_graph(&mut thread_rng(), &peers, 2, 1, GraphType::LAModel);
+        assert!(is_connected(&la.graph));
+        assert_eq!(la.bridges, vec![peers[0].clone(), peers[10].clone()]);
+    }
+
+    #[test]
+    pub fn test_gateway_graph() {
+        let peers = to_peer_ids_vec(0..20);
+        let (graph, gateways) = gateway_graph(&peers, 3, 2);
+        assert_eq!(gateways, peers[..3].to_vec());
+        assert!(is_connected(&graph));
+        for (i, u) in peers.iter().enumerate() {
+            let links = graph.undirected_links(u).unwrap();
+            if i < 3 {
+                // Other gateways, plus 2 of every 3 edge devices
+                assert!(links.len() == 2 + 11 || links.len() == 2 + 12);
+            } else {
+                assert_eq!(links.len(), 2);
+                assert!(links.iter().all(|v| gateways.contains(v)));
+            }
+        }
+        // Can't have more uplinks than gateways
+        let (single, _) = gateway_graph(&peers, 1, 3);
+        assert_eq!(single.undirected_links(&peers[0]).unwrap().len(), 19);
+    }
+
+    #[test]
+    pub fn test_hierarchy_graph() {
+        let peers = to_peer_ids_vec(0..20);
+        let two_tier = hierarchy_graph(&peers, &[5, 4]);
+        assert_eq!(two_tier.teams.len(), 4);
+        assert!(is_connected(&two_tier.graph));
+        for team in &two_tier.teams {
+            assert_eq!(team.len(), 5);
+            for u in &team[1..] {
+                assert_eq!(two_tier.tiers[u], 0);
+                // Only linked within the team
+                assert_eq!(two_tier.graph.undirected_links(u).unwrap().len(), 4);
+            }
+            assert_eq!(two_tier.tiers[&team[0]], 1);
+        }
+
+        // 7 teams, then 4, 2 and 1 groups of leaders
+        let deep = hierarchy_graph(&peers, &[3, 2]);
+        assert_eq!(deep.teams.len(), 7);
+        assert!(is_connected(&deep.graph));
+        assert_eq!(deep.tiers[&peers[0]], 3);
+        assert_eq!(deep.tiers.values().filter(|t| **t == 1).count(), 3);
+
+        let single = hierarchy_graph(&peers[..1], &[5]);
+        assert_eq!(single.teams, vec![peers[..1].to_vec()]);
+    }
+
+    #[test]
+    pub fn test_enforce_max_degree() {
+        let max_degree = |g: &PeerGraph| {
+            g.nmap
+                .keys()
+                .map(|u| g.undirected_links(u).unwrap().len())
+                .max()
+                .unwrap()
+        };
+        let peers = to_peer_ids_vec(0..60);
+        let mut graphs = [
+            local_attachment_model(&mut thread_rng(), &peers, 4),
+            barabasi_albert(&mut thread_rng(), &peers, 2),
+            complete_graph(&peers[..10]),
+            star_graph(&peers[..10], &peers[0]),
+            spanning_tree(&peers, 3),
+        ];
+        for graph in graphs.iter_mut() {
+            assert!(enforce_max_degree(graph, 3).is_empty());
+            assert!(max_degree(graph) <= 3);
+            assert!(is_connected(graph));
+        }
+        // Nowhere for the leaves' links to go
+        let mut star = star_graph(&peers[..10], &peers[0]);
+        assert_eq!(enforce_max_degree(&mut star, 1), vec![peers[0].clone()]);
+        assert!(is_connected(&star));
+    }
+
+    #[test]
+    pub fn test_orient_edges() {
+        let out_degrees = |g: &PeerGraph| -> Vec<usize> {
+            let mut ids: Vec<&PeerId> = g.nmap.keys().collect();
+            ids.sort();
+            ids.iter().map(|u| g.nmap[*u].len()).collect()
+        };
+        let peers = to_peer_ids_vec(0..10);
+        let star = star_graph(&peers, &peers[0]);
+        let num_edges = |g: &PeerGraph| g.nmap.values().map(|n| n.len()).sum::<usize>();
+
+        for orientation in [
+            EdgeOrientation::AsGenerated,
+            EdgeOrientation::Balanced,
+            EdgeOrientation::Random,
+            EdgeOrientation::LowerIdDials,
+            EdgeOrientation::HubListens,
+        ] {
+            let mut graph = star.clone();
+            orient_edges(&mut thread_rng(), &mut graph, orientation);
+            assert_eq!(num_edges(&graph), 9);
+            for p in &peers[1..] {
+                assert!(graph.has_edge(p, &peers[0]));
+            }
+        }
+
+        let mut lower = star.clone();
+        orient_edges(&mut thread_rng(), &mut lower, EdgeOrientation::LowerIdDials);
+        assert_eq!(lower.nmap[&peers[0]].len(), 9);
+        let mut hub = lower.clone();
+        orient_edges(&mut thread_rng(), &mut hub, EdgeOrientation::HubListens);
+        assert!(hub.nmap[&peers[0]].is_empty());
+
+        // A complete graph on 9 peers can give every peer exactly 4 dials
+        let mut balanced = complete_graph(&peers[..9]);
+        orient_edges(&mut thread_rng(), &mut balanced, EdgeOrientation::Balanced);
+        assert_eq!(num_edges(&balanced), 36);
+        assert!(out_degrees(&balanced).iter().all(|d| *d == 4));
+    }
+
+    #[test]
+    pub fn test_grid_dimensions() {
+        assert_eq!(grid_dimensions(16, None, None), (4, 4));
+        assert_eq!(grid_dimensions(10, None, None), (3, 4));
+        assert_eq!(grid_dimensions(10, Some(2), None), (2, 5));
+        assert_eq!(grid_dimensions(10, None, Some(3)), (4, 3));
+        // Too small for all peers: add rows
+        assert_eq!(grid_dimensions(10, Some(2), Some(3)), (4, 3));
+    }
+
+    #[test]
+    pub fn test_lattice() {
+        // 3 x 4 grid with a partial last row:
+        // 0 1 2 3
+        // 4 5 6 7
+        // 8 9
+        let peers = to_peer_ids_vec(0..10);
+        let grid = lattice(&peers, 3, 4, false);
+        let degree = |g: &PeerGraph, i: usize| g.undirected_links(&peers[i]).unwrap().len();
+        assert!(is_connected(&grid));
+        assert_eq!(grid.nmap.values().map(|n| n.len()).sum::<usize>(), 13);
+        assert_eq!(degree(&grid, 0), 2);
+        assert_eq!(degree(&grid, 5), 4);
+        assert_eq!(degree(&grid, 7), 2);
+        assert_eq!(degree(&grid, 9), 2);
+
+        let torus = lattice(&peers, 3, 4, true);
+        assert!(torus.has_edge(&peers[3], &peers[0]));
+        assert!(torus.has_edge(&peers[9], &peers[8]));
+        assert!(torus.has_edge(&peers[9], &peers[1]));
+        assert!(torus.has_edge(&peers[6], &peers[2]));
+        for i in 0..10 {
+            assert!(degree(&torus, i) >= 3);
+        }
+
+        // Single row torus is a ring
+        let ring = lattice(&peers[..5], 1, 5, true);
+        for p in &peers[..5] {
+            assert_eq!(ring.undirected_links(p).unwrap().len(), 2);
+        }
+    }
+
+    #[test]
+    pub fn test_geometric_graph() {
+        let peers = to_peer_ids_vec(0..40);
+        // Range too small to ever connect by chance, so this exercises the repair step
+        for range in [10.0, 250.0] {
+            let (graph, positions) = geometric_graph(&mut thread_rng(), &peers, 1000.0, range);
+            assert_eq!(positions.len(), 40);
+            assert!(is_connected(&graph));
+            for u in &peers {
+                let p = positions[u];
+                assert!((0.0..=1000.0).contains(&p.x) && (0.0..=1000.0).contains(&p.y));
+                for v in graph.undirected_links(u).unwrap() {
+                    assert!(p.distance(&positions[&v]) <= range);
+                }
+            }
+        }
+    }
+
+    #[test]
+    pub fn test_seeded_graphs() {
+        let peers = to_peer_ids_vec(0..40);
+        let build = |seed: u64| {
+            let mut rng = StdRng::seed_from_u64(seed);
+            let la = local_attachment_model(&mut rng, &peers, 4);
+            let random = random_graph(&mut rng, &peers, 0.05);
+            let ws = small_world(&mut rng, &peers, 4, 0.3);
+            let (geo, positions) = geometric_graph(&mut rng, &peers, 1000.0, 100.0);
+            (la.nmap, random.nmap, ws.nmap, geo.nmap, positions)
+        };
+        assert_eq!(build(7), build(7));
+        assert_ne!(build(7), build(8));
+    }
+
+    #[test]
+    pub fn test_peer_graph_links() {
+        let peers = to_peer_ids_vec(0..200);
+        let mut graph = local_attachment_model(&mut thread_rng(), &peers, 3);
+        graph.remove_edge(&peers[0], &peers[1]);
+        for (u, neighbors) in &graph.nmap {
+            for v in neighbors {
+                assert!(graph.links(u).unwrap().contains(v));
+                assert!(graph.links(v).unwrap().contains(u));
+            }
+        }
+        for u in &peers {
+            let mut expected: HashSet<&PeerId> = graph.nmap[u].iter().collect();
+            expected.extend(peers.iter().filter(|v| graph.nmap[*v].contains(u)));
+            assert_eq!(graph.degree(u), expected.len());
+        }
+
+        // Serialized form is still the plain directed map, and links are rebuilt on load
+        let json = serde_json::to_value(&graph).unwrap();
+        assert_eq!(json, serde_json::to_value(&graph.nmap).unwrap());
+        let loaded: PeerGraph = serde_json::from_value(json).unwrap();
+        assert_eq!(loaded.nmap, graph.nmap);
+        for u in &peers {
+            assert_eq!(loaded.links(u), graph.links(u));
+        }
+    }
+
     #[test]
     pub fn test_graphs_to_dot() {
         let peers = to_peer_ids_vec(0..30);
 
         let complete = complete_graph(&peers);
         let spanning = spanning_tree(&peers, 4);
-        let la_model = local_attachment_model(&peers, 4);
+        let la_model = local_attachment_model(&mut thread_rng(), &peers, 4);
+        let to_dot = |g: PeerGraph| export::to_dot(&g, &export::GraphAttributes::new());
 
         File::create("complete.dot")
             .unwrap()
-            .write_all(complete.to_dot().as_bytes())
+            .write_all(to_dot(complete).as_bytes())
             .unwrap();
         File::create("spanning.dot")
             .unwrap()
-            .write_all(spanning.to_dot().as_bytes())
+            .write_all(to_dot(spanning).as_bytes())
             .unwrap();
         File::create("la_model.dot")
             .unwrap()
-            .write_all(la_model.to_dot().as_bytes())
+            .write_all(to_dot(la_model).as_bytes())
             .unwrap();
     }
 }
@@ -0,0 +1,409 @@
+use serde_derive::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, VecDeque};
+
+use crate::types::*;
+
+// Structural statistics for a PeerGraph. As with undirected_links(), edges are treated as
+// undirected: u -> v only says which side dials.
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct GraphStats {
+    pub num_peers: usize,
+    pub num_edges: usize,
+    // Sizes of connected components, largest first
+    pub component_sizes: Vec<usize>,
+    // Longest shortest path, and the mean shortest path length, over all pairs of peers that
+    // can reach each other
+    pub diameter: usize,
+    pub avg_shortest_path: f64,
+    // Degree -> number of peers with that degree
+    pub degree_distribution: BTreeMap<usize, usize>,
+    // Mean of the local clustering coefficients
+    pub clustering_coefficient: f64,
+    // Peers whose removal would disconnect the graph
+    pub articulation_points: Vec<PeerId>,
+    // Fewest peers whose removal disconnects the graph
+    pub vertex_connectivity: usize,
+    // Most links any one peer dials, and most any one peer listens for
+    pub max_dials: usize,
+    pub max_listens: usize,
+}
+
+// Vertices in sorted order, plus undirected adjacency lists by vertex index. Lets us run
+// traversals without rescanning the graph for each vertex's links.
+struct Indexed<'a> {
+    ids: Vec<&'a PeerId>,
+    adj: Vec<Vec<usize>>,
+}
+
+impl<'a> Indexed<'a> {
+    fn new(graph: &'a PeerGraph) -> Self {
+        let mut ids: Vec<&PeerId> = graph.nmap.keys().collect();
+        ids.sort();
+        let index: HashMap<&PeerId, usize> = ids.iter().enumerate().map(|(i, p)| (*p, i)).collect();
+        let adj = ids
+            .iter()
+            .enumerate()
+            .map(|(i, u)| {
+                let links = graph.links(u).unwrap().iter().map(|v| index[v]);
+                let mut a: Vec<usize> = links.filter(|j| *j != i).collect();
+                a.sort_unstable();
+                a
+            })
+            .collect();
+        Indexed { ids, adj }
+    }
+
+    // Hop counts from start to every vertex, None where unreachable
+    fn bfs(&self, start: usize) -> Vec<Option<usize>> {
+        let mut dist = vec![None; self.ids.len()];
+        dist[start] = Some(0);
+        let mut queue = VecDeque::from([start]);
+        while let Some(u) = queue.pop_front() {
+            let d = dist[u].unwrap();
+            for &w in &self.adj[u] {
+                if dist[w].is_none() {
+                    dist[w] = Some(d + 1);
+                    queue.push_back(w);
+                }
+            }
+        }
+        dist
+    }
+}
+
+pub fn connected_components(graph: &PeerGraph) -> Vec<Vec<PeerId>> {
+    let g = Indexed::new(graph);
+    let mut seen = vec![false; g.ids.len()];
+    let mut comps = Vec::new();
+    for start in 0..g.ids.len() {
+        if seen[start] {
+            continue;
+        }
+        let mut comp = Vec::new();
+        let mut queue = VecDeque::from([start]);
+        seen[start] = true;
+        while let Some(u) = queue.pop_front() {
+            for &w in &g.adj[u] {
+                if !seen[w] {
+                    seen[w] = true;
+                    queue.push_back(w);
+                }
+            }
+            comp.push(g.ids[u].clone());
+        }
+        comps.push(comp);
+    }
+    comps
+}
+
+pub fn is_connected(graph: &PeerGraph) -> bool {
+    connected_components(graph).len() <= 1
+}
+
+// Diameter and average shortest path length, over reachable pairs only. BFS from every vertex,
+// so O(V * E).
+pub fn path_lengths(graph: &PeerGraph) -> (usize, f64) {
+    let g = Indexed::new(graph);
+    let (mut diameter, mut total, mut pairs) = (0, 0, 0);
+    for start in 0..g.ids.len() {
+        for d in g.bfs(start).into_iter().flatten().filter(|d| *d > 0) {
+            diameter = diameter.max(d);
+            total += d;
+            pairs += 1;
+        }
+    }
+    let avg = if pairs > 0 {
+        total as f64 / pairs as f64
+    } else {
+        0.0
+    };
+    (diameter, avg)
+}
+
+pub fn degree_distribution(graph: &PeerGraph) -> BTreeMap<usize, usize> {
+    let g = Indexed::new(graph);
+    let mut dist = BTreeMap::new();
+    for a in &g.adj {
+        *dist.entry(a.len()).or_insert(0) += 1;
+    }
+    dist
+}
+
+// Average over all vertices of the fraction of pairs of neighbors that are linked themselves.
+// Vertices with fewer than two neighbors count as 0.
+pub fn clustering_coefficient(graph: &PeerGraph) -> f64 {
+    let g = Indexed::new(graph);
+    if g.ids.is_empty() {
+        return 0.0;
+    }
+    let mut sum = 0.0;
+    for a in &g.adj {
+        let k = a.len();
+        if k < 2 {
+            continue;
+        }
+        let mut links = 0;
+        for (i, &u) in a.iter().enumerate() {
+            for &v in &a[i + 1..] {
+                if g.adj[u].binary_search(&v).is_ok() {
+                    links += 1;
+                }
+            }
+        }
+        sum += 2.0 * links as f64 / (k * (k - 1)) as f64;
+    }
+    sum / g.ids.len() as f64
+}
+
+// Tarjan's articulation point algorithm, with an explicit stack so large graphs don't overflow
+// the call stack.
+pub fn articulation_points(graph: &PeerGraph) -> Vec<PeerId> {
+    let g = Indexed::new(graph);
+    let n = g.ids.len();
+    let mut disc = vec![usize::MAX; n];
+    let mut low = vec![0; n];
+    let mut is_cut = vec![false; n];
+    let mut time = 0;
+    for root in 0..n {
+        if disc[root] != usize::MAX {
+            continue;
+        }
+        disc[root] = time;
+        low[root] = time;
+        time += 1;
+        let mut root_children = 0;
+        // (vertex, parent, index of next neighbor to visit)
+        let mut stack = vec![(root, usize::MAX, 0)];
+        while let Some(&mut (u, parent, ref mut next)) = stack.last_mut() {
+            if let Some(&w) = g.adj[u].get(*next) {
+                *next += 1;
+                if disc[w] == usize::MAX {
+                    disc[w] = time;
+                    low[w] = time;
+                    time += 1;
+                    if u == root {
+                        root_children += 1;
+                    }
+                    stack.push((w, u, 0));
+                } else if w != parent {
+                    low[u] = low[u].min(disc[w]);
+                }
+            } else {
+                stack.pop();
+                if parent != usize::MAX {
+                    low[parent] = low[parent].min(low[u]);
+                    if parent != root && low[u] >= disc[parent] {
+                        is_cut[parent] = true;
+                    }
+                }
+            }
+        }
+        is_cut[root] = root_children > 1;
+    }
+    (0..n)
+        .filter(|i| is_cut[*i])
+        .map(|i| g.ids[i].clone())
+        .collect()
+}
+
+// Number of vertex-disjoint paths between non-adjacent vertices s and t, counting no further than
+// limit. Max flow where each vertex v other than s and t is split into v_in (2v) and v_out
+// (2v + 1) joined by an edge of capacity 1, so that at most one path can pass through it.
+fn local_connectivity(g: &Indexed, s: usize, t: usize, limit: usize) -> usize {
+    let n = g.ids.len();
+    // Edge e's reverse is e ^ 1
+    let mut to: Vec<usize> = Vec::new();
+    let mut cap: Vec<usize> = Vec::new();
+    let mut out: Vec<Vec<usize>> = vec![Vec::new(); 2 * n];
+    let mut add = |u: usize, v: usize, c: usize| {
+        out[u].push(to.len());
+        to.push(v);
+        cap.push(c);
+        out[v].push(to.len());
+        to.push(u);
+        cap.push(0);
+    };
+    for v in 0..n {
+        let c = if v == s || v == t { n } else { 1 };
+        add(2 * v, 2 * v + 1, c);
+        for &w in &g.adj[v] {
+            add(2 * v + 1, 2 * w, 1);
+        }
+    }
+    let (source, sink) = (2 * s + 1, 2 * t);
+    let mut flow = 0;
+    while flow < limit {
+        // BFS for an augmenting path, remembering the edge we reached each node by
+        let mut via = vec![usize::MAX; 2 * n];
+        let mut queue = VecDeque::from([source]);
+        while let Some(u) = queue.pop_front() {
+            for &e in &out[u] {
+                let v = to[e];
+                if cap[e] > 0 && v != source && via[v] == usize::MAX {
+                    via[v] = e;
+                    queue.push_back(v);
+                }
+            }
+        }
+        if via[sink] == usize::MAX {
+            break;
+        }
+        let mut v = sink;
+        while v != source {
+            let e = via[v];
+            cap[e] -= 1;
+            cap[e ^ 1] += 1;
+            v = to[e ^ 1];
+        }
+        flow += 1;
+    }
+    flow
+}
+
+// Smallest number of peers whose removal disconnects the graph (or leaves a single peer).
+// Follows Even's algorithm: some vertex among the first k + 1 is outside any minimum separator of
+// size k, so it is enough to check pairs that include one of those.
+pub fn vertex_connectivity(graph: &PeerGraph) -> usize {
+    let g = Indexed::new(graph);
+    let n = g.ids.len();
+    if n <= 1 {
+        return 0;
+    }
+    // Settle k < 2 with linear-time checks; max flow over every pair is slow on large meshes
+    if !is_connected(graph) {
+        return 0;
+    }
+    if n == 2 || !articulation_points(graph).is_empty() {
+        return 1;
+    }
+    let mut k = g.adj.iter().map(|a| a.len()).min().unwrap();
+    let mut i = 0;
+    while i <= k && i < n {
+        for j in i + 1..n {
+            if g.adj[i].binary_search(&j).is_err() {
+                k = k.min(local_connectivity(&g, i, j, k));
+            }
+        }
+        i += 1;
+    }
+    k
+}
+
+// True if the graph has more than k peers and stays connected after removing any k - 1 of them
+pub fn is_k_connected(graph: &PeerGraph, k: usize) -> bool {
+    graph.nmap.len() > k && vertex_connectivity(graph) >= k
+}
+
+// Largest out-degree and in-degree, i.e. the most links one peer dials or accepts
+pub fn max_dials_listens(graph: &PeerGraph) -> (usize, usize) {
+    let mut listens: HashMap<&PeerId, usize> = HashMap::new();
+    let mut max_dials = 0;
+    for neighbors in graph.nmap.values() {
+        max_dials = max_dials.max(neighbors.len());
+        for v in neighbors {
+            *listens.entry(v).or_insert(0) += 1;
+        }
+    }
+    (max_dials, listens.values().copied().max().unwrap_or(0))
+}
+
+pub fn graph_stats(graph: &PeerGraph) -> GraphStats {
+    let mut component_sizes: Vec<usize> = connected_components(graph)
+        .iter()
+        .map(|c| c.len())
+        .collect();
+    component_sizes.sort_by(|a, b| b.cmp(a));
+    let degree_distribution = degree_distribution(graph);
+    let (diameter, avg_shortest_path) = path_lengths(graph);
+    let (max_dials, max_listens) = max_dials_listens(graph);
+    GraphStats {
+        num_peers: graph.nmap.len(),
+        num_edges: degree_distribution
+            .iter()
+            .map(|(d, n)| d * n)
+            .sum::<usize>()
+            / 2,
+        component_sizes,
+        diameter,
+        avg_shortest_path,
+        degree_distribution,
+        clustering_coefficient: clustering_coefficient(graph),
+        articulation_points: articulation_points(graph),
+        vertex_connectivity: vertex_connectivity(graph),
+        max_dials,
+        max_listens,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::graph::test_util::to_peer_ids_vec;
+    use crate::graph::*;
+
+    #[test]
+    fn test_line_stats() {
+        let peers = to_peer_ids_vec(0..5);
+        let stats = graph_stats(&line_graph(&peers));
+        assert_eq!(stats.num_peers, 5);
+        assert_eq!(stats.num_edges, 4);
+        assert_eq!(stats.component_sizes, vec![5]);
+        assert_eq!(stats.diameter, 4);
+        // 4 pairs at distance 1, 3 at 2, 2 at 3, 1 at 4
+        assert_eq!(stats.avg_shortest_path, 20.0 / 10.0);
+        assert_eq!(stats.degree_distribution, BTreeMap::from([(1, 2), (2, 3)]));
+        assert_eq!(stats.clustering_coefficient, 0.0);
+        assert_eq!(stats.articulation_points, to_peer_ids_vec(1..4));
+    }
+
+    #[test]
+    fn test_complete_and_star_stats() {
+        let peers = to_peer_ids_vec(0..6);
+        let complete = graph_stats(&complete_graph(&peers));
+        assert_eq!(complete.num_edges, 15);
+        assert_eq!(complete.diameter, 1);
+        assert_eq!(complete.clustering_coefficient, 1.0);
+        assert!(complete.articulation_points.is_empty());
+
+        let star = graph_stats(&star_graph(&peers, &peers[2]));
+        assert_eq!(star.diameter, 2);
+        assert_eq!((star.max_dials, star.max_listens), (1, 5));
+        assert_eq!(star.articulation_points, vec![peers[2].clone()]);
+        assert!(articulation_points(&ring_graph(&peers)).is_empty());
+    }
+
+    #[test]
+    fn test_vertex_connectivity() {
+        let peers = to_peer_ids_vec(0..8);
+        assert_eq!(vertex_connectivity(&complete_graph(&peers)), 7);
+        assert_eq!(vertex_connectivity(&line_graph(&peers)), 1);
+        assert_eq!(vertex_connectivity(&ring_graph(&peers)), 2);
+        assert_eq!(vertex_connectivity(&star_graph(&peers, &peers[0])), 1);
+        assert_eq!(vertex_connectivity(&lattice(&peers, 2, 4, true)), 3);
+        assert_eq!(vertex_connectivity(&PeerGraph::new()), 0);
+
+        // Two 4-cliques sharing two peers come apart when both are removed
+        let mut graph = complete_graph(&peers[..4]);
+        graph.merge(complete_graph(&peers[2..6]));
+        assert_eq!(vertex_connectivity(&graph), 2);
+        assert!(is_k_connected(&graph, 2));
+        assert!(!is_k_connected(&graph, 3));
+
+        graph.add_vertex(&peers[6]);
+        assert_eq!(vertex_connectivity(&graph), 0);
+    }
+
+    #[test]
+    fn test_disconnected_stats() {
+        let mut graph = complete_graph(&to_peer_ids_vec(0..3));
+        graph.merge(line_graph(&to_peer_ids_vec(3..5)));
+        graph.add_vertex(&"5".to_string());
+        assert!(!is_connected(&graph));
+        let stats = graph_stats(&graph);
+        assert_eq!(stats.component_sizes, vec![3, 2, 1]);
+        assert_eq!(stats.diameter, 1);
+        // The triangle's three vertices have clustering 1, the other three 0
+        assert_eq!(stats.clustering_coefficient, 0.5);
+    }
+}
@@ -0,0 +1,535 @@
+use serde_json::{json, Map, Value};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Display;
+
+use crate::types::*;
+
+// Writing connection graphs with per-node and per-edge attributes, for tools like Gephi and
+// Python notebooks. Every format keeps the edge direction (u -> v: u calls connect()) and uses
+// the full peer ID as the node ID, with the short ID as its label.
+//
+// - DOT: attributes as extra graph, node and edge attributes. Graphviz ignores the ones it
+//   doesn't know, and uses the rest (e.g. layout and pos).
+// - GraphML: a <key> per attribute, typed so numbers stay numbers.
+// - JSON: networkx's adjacency format, readable with networkx.adjacency_graph().
+// - Mermaid: node and edge attributes are folded into labels. Graph attributes are left out.
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum AttrValue {
+    Text(String),
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+}
+
+impl Display for AttrValue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AttrValue::Text(s) => write!(f, "{}", s),
+            AttrValue::Bool(b) => write!(f, "{}", b),
+            AttrValue::Int(i) => write!(f, "{}", i),
+            AttrValue::Float(x) => write!(f, "{}", x),
+        }
+    }
+}
+
+impl From<&str> for AttrValue {
+    fn from(s: &str) -> Self {
+        AttrValue::Text(s.to_string())
+    }
+}
+
+impl From<String> for AttrValue {
+    fn from(s: String) -> Self {
+        AttrValue::Text(s)
+    }
+}
+
+impl From<bool> for AttrValue {
+    fn from(b: bool) -> Self {
+        AttrValue::Bool(b)
+    }
+}
+
+impl From<u64> for AttrValue {
+    fn from(i: u64) -> Self {
+        AttrValue::Int(i as i64)
+    }
+}
+
+impl From<usize> for AttrValue {
+    fn from(i: usize) -> Self {
+        AttrValue::Int(i as i64)
+    }
+}
+
+impl From<f64> for AttrValue {
+    fn from(x: f64) -> Self {
+        AttrValue::Float(x)
+    }
+}
+
+pub type Attributes = BTreeMap<String, AttrValue>;
+
+#[derive(Clone, Debug, Default)]
+pub struct GraphAttributes {
+    pub graph: Attributes,
+    pub nodes: HashMap<PeerId, Attributes>,
+    // Keyed by (dialer, listener), as in PeerGraph
+    pub edges: HashMap<(PeerId, PeerId), Attributes>,
+}
+
+impl GraphAttributes {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn set_graph(&mut self, key: &str, value: impl Into<AttrValue>) {
+        self.graph.insert(key.to_string(), value.into());
+    }
+
+    pub fn set_node(&mut self, peer: &PeerId, key: &str, value: impl Into<AttrValue>) {
+        self.nodes
+            .entry(peer.clone())
+            .or_default()
+            .insert(key.to_string(), value.into());
+    }
+
+    pub fn set_edge(&mut self, u: &PeerId, v: &PeerId, key: &str, value: impl Into<AttrValue>) {
+        self.edges
+            .entry((u.clone(), v.clone()))
+            .or_default()
+            .insert(key.to_string(), value.into());
+    }
+
+    // What the plan and heartbeats tell us about each peer: its state, address and whatever
+    // labels the topology gave it
+    pub fn from_peers<'a>(peers: impl IntoIterator<Item = &'a Peer>) -> Self {
+        let mut attrs = Self::new();
+        for p in peers {
+            let id = &p.peer_id;
+            attrs.set_node(id, "state", p.state.to_string());
+            attrs.set_node(id, "addr", format!("{}:{}", p.peer_ip_addr, p.peer_port));
+            if let Some(group) = &p.group {
+                attrs.set_node(id, "group", group.as_str());
+            }
+            if let Some(role) = p.role {
+                attrs.set_node(id, "role", format!("{:?}", role));
+            }
+            if let Some(tier) = p.tier {
+                attrs.set_node(id, "tier", tier);
+            }
+            if let Some(pos) = p.position {
+                attrs.set_node(id, "x", pos.x);
+                attrs.set_node(id, "y", pos.y);
+            }
+        }
+        attrs
+    }
+
+    // What the plan tells us about each link: whether it crosses groups and the roles of the
+    // peers at its ends, where the topology gave them any
+    pub fn set_links<'a>(&mut self, graph: &PeerGraph, peers: impl IntoIterator<Item = &'a Peer>) {
+        let peers: HashMap<&PeerId, &Peer> = peers.into_iter().map(|p| (&p.peer_id, p)).collect();
+        for (u, neighbors) in &graph.nmap {
+            for v in neighbors {
+                let (pu, pv) = match (peers.get(u), peers.get(v)) {
+                    (Some(pu), Some(pv)) => (pu, pv),
+                    _ => continue,
+                };
+                if let (Some(gu), Some(gv)) = (&pu.group, &pv.group) {
+                    self.set_edge(u, v, "cross_group", gu != gv);
+                }
+                if let (Some(ru), Some(rv)) = (pu.role, pv.role) {
+                    self.set_edge(u, v, "roles", format!("{:?}-{:?}", ru, rv));
+                }
+            }
+        }
+    }
+
+    // Pins each vertex to a fixed position for Graphviz's neato layout engine. Positions are
+    // scaled so the drawing is about sqrt(n) inches across, whatever their units.
+    pub fn pin_positions(&mut self, positions: &HashMap<PeerId, Position>) {
+        self.set_graph("layout", "neato");
+        let extent = positions
+            .values()
+            .flat_map(|p| [p.x, p.y])
+            .fold(0.0f64, |a, b| a.max(b.abs()));
+        let scale = if extent > 0.0 {
+            72.0 * (positions.len() as f64).sqrt() / extent
+        } else {
+            1.0
+        };
+        for (u, p) in positions {
+            // dot's y axis points up, ours points down
+            let pos = format!("{:.1},{:.1}!", p.x * scale, -p.y * scale);
+            self.set_node(u, "pos", pos);
+        }
+    }
+
+    fn node(&self, peer: &PeerId) -> Option<&Attributes> {
+        self.nodes.get(peer)
+    }
+
+    fn edge(&self, u: &PeerId, v: &PeerId) -> Option<&Attributes> {
+        self.edges.get(&(u.clone(), v.clone()))
+    }
+}
+
+// Vertices and directed edges in sorted order, so exports are stable
+fn sorted_graph(graph: &PeerGraph) -> (Vec<&PeerId>, Vec<(&PeerId, &PeerId)>) {
+    let mut nodes: Vec<&PeerId> = graph.nmap.keys().collect();
+    nodes.sort();
+    let mut edges: Vec<(&PeerId, &PeerId)> = graph
+        .nmap
+        .iter()
+        .flat_map(|(u, neighbors)| neighbors.iter().map(move |v| (u, v)))
+        .collect();
+    edges.sort();
+    (nodes, edges)
+}
+
+fn quote(s: &str) -> String {
+    format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+fn dot_attrs(label: Option<String>, attrs: Option<&Attributes>) -> String {
+    let mut items: Vec<String> = label
+        .iter()
+        .map(|l| format!("label={}", quote(l)))
+        .collect();
+    for (k, v) in attrs.into_iter().flatten() {
+        items.push(format!("{}={}", quote(k), quote(&v.to_string())));
+    }
+    if items.is_empty() {
+        String::new()
+    } else {
+        format!(" [{}]", items.join(", "))
+    }
+}
+
+pub fn to_dot(graph: &PeerGraph, attrs: &GraphAttributes) -> String {
+    let (nodes, edges) = sorted_graph(graph);
+    let mut dot = String::new();
+    dot.push_str("digraph G {\n");
+    for (k, v) in &attrs.graph {
+        dot.push_str(&format!("  {}={};\n", quote(k), quote(&v.to_string())));
+    }
+    for u in nodes {
+        let label = Some(short_peer_id(u));
+        dot.push_str(&format!(
+            "  {}{};\n",
+            quote(u),
+            dot_attrs(label, attrs.node(u))
+        ));
+    }
+    for (u, v) in edges {
+        let a = dot_attrs(None, attrs.edge(u, v));
+        dot.push_str(&format!("  {} -> {}{};\n", quote(u), quote(v), a));
+    }
+    dot.push_str("}\n");
+    dot
+}
+
+fn xml_escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+// GraphML type for an attribute: boolean if all values are booleans, double if they are
+// numbers and any is a float, long if all are integers, otherwise string
+fn graphml_type<'a>(values: impl Iterator<Item = &'a AttrValue>) -> &'static str {
+    let mut ty = None;
+    for v in values {
+        let t = match v {
+            AttrValue::Text(_) => return "string",
+            AttrValue::Bool(_) => "boolean",
+            AttrValue::Int(_) => "long",
+            AttrValue::Float(_) => "double",
+        };
+        ty = match (ty, t) {
+            (None, t) => Some(t),
+            (Some(a), b) if a == b => Some(a),
+            (Some("long"), "double") | (Some("double"), "long") => Some("double"),
+            _ => return "string",
+        };
+    }
+    ty.unwrap_or("long")
+}
+
+fn graphml_keys(
+    out: &mut String,
+    domain: &str,
+    prefix: &str,
+    attrs: &[&Attributes],
+) -> BTreeMap<String, String> {
+    let mut names: BTreeMap<&String, Vec<&AttrValue>> = BTreeMap::new();
+    for a in attrs {
+        for (k, v) in a.iter() {
+            names.entry(k).or_default().push(v);
+        }
+    }
+    let mut ids = BTreeMap::new();
+    for (i, (name, values)) in names.into_iter().enumerate() {
+        let id = format!("{}{}", prefix, i);
+        out.push_str(&format!(
+            "  <key id=\"{}\" for=\"{}\" attr.name=\"{}\" attr.type=\"{}\"/>\n",
+            id,
+            domain,
+            xml_escape(name),
+            graphml_type(values.into_iter())
+        ));
+        ids.insert(name.clone(), id);
+    }
+    ids
+}
+
+fn graphml_data(out: &mut String, ids: &BTreeMap<String, String>, attrs: Option<&Attributes>) {
+    for (k, v) in attrs.into_iter().flatten() {
+        let value = xml_escape(&v.to_string());
+        out.push_str(&format!(
+            "      <data key=\"{}\">{}</data>\n",
+            ids[k], value
+        ));
+    }
+}
+
+pub fn to_graphml(graph: &PeerGraph, attrs: &GraphAttributes) -> String {
+    let (nodes, edges) = sorted_graph(graph);
+    // Short ID as a label attribute, which Gephi picks up
+    let labels: Vec<Attributes> = nodes
+        .iter()
+        .map(|u| {
+            let mut a = attrs.node(u).cloned().unwrap_or_default();
+            a.insert("label".to_string(), short_peer_id(u).into());
+            a
+        })
+        .collect();
+    let edge_attrs: Vec<&Attributes> = edges.iter().filter_map(|(u, v)| attrs.edge(u, v)).collect();
+
+    let mut out = String::new();
+    out.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    out.push_str("<graphml xmlns=\"http://graphml.graphdrawing.org/xmlns\">\n");
+    let graph_ids = graphml_keys(&mut out, "graph", "g", &[&attrs.graph]);
+    let node_ids = graphml_keys(&mut out, "node", "n", &labels.iter().collect::<Vec<_>>());
+    let edge_ids = graphml_keys(&mut out, "edge", "e", &edge_attrs);
+    out.push_str("  <graph id=\"G\" edgedefault=\"directed\">\n");
+    graphml_data(&mut out, &graph_ids, Some(&attrs.graph));
+    for (u, a) in nodes.iter().zip(&labels) {
+        out.push_str(&format!("    <node id=\"{}\">\n", xml_escape(u)));
+        graphml_data(&mut out, &node_ids, Some(a));
+        out.push_str("    </node>\n");
+    }
+    for (u, v) in edges {
+        out.push_str(&format!(
+            "    <edge source=\"{}\" target=\"{}\">\n",
+            xml_escape(u),
+            xml_escape(v)
+        ));
+        graphml_data(&mut out, &edge_ids, attrs.edge(u, v));
+        out.push_str("    </edge>\n");
+    }
+    out.push_str("  </graph>\n");
+    out.push_str("</graphml>\n");
+    out
+}
+
+fn json_attrs(obj: &mut Map<String, Value>, attrs: Option<&Attributes>) {
+    for (k, v) in attrs.into_iter().flatten() {
+        let value = match v {
+            AttrValue::Text(s) => json!(s),
+            AttrValue::Bool(b) => json!(b),
+            AttrValue::Int(i) => json!(i),
+            AttrValue::Float(x) => json!(x),
+        };
+        obj.insert(k.clone(), value);
+    }
+}
+
+pub fn to_json_adjacency(graph: &PeerGraph, attrs: &GraphAttributes) -> Value {
+    let (nodes, _) = sorted_graph(graph);
+    let mut graph_attrs = Map::new();
+    json_attrs(&mut graph_attrs, Some(&attrs.graph));
+    let mut node_list = Vec::new();
+    let mut adjacency = Vec::new();
+    for u in nodes {
+        let mut node = Map::new();
+        json_attrs(&mut node, attrs.node(u));
+        node.insert("label".to_string(), json!(short_peer_id(u)));
+        node.insert("id".to_string(), json!(u));
+        node_list.push(Value::Object(node));
+
+        let mut neighbors: Vec<&PeerId> = graph.nmap[u].iter().collect();
+        neighbors.sort();
+        let links: Vec<Value> = neighbors
+            .into_iter()
+            .map(|v| {
+                let mut link = Map::new();
+                json_attrs(&mut link, attrs.edge(u, v));
+                link.insert("id".to_string(), json!(v));
+                Value::Object(link)
+            })
+            .collect();
+        adjacency.push(Value::Array(links));
+    }
+    json!({
+        "directed": true,
+        "multigraph": false,
+        "graph": graph_attrs,
+        "nodes": node_list,
+        "adjacency": adjacency,
+    })
+}
+
+fn mermaid_label(head: Option<String>, attrs: Option<&Attributes>) -> Option<String> {
+    let mut lines: Vec<String> = head.into_iter().collect();
+    for (k, v) in attrs.into_iter().flatten() {
+        lines.push(format!("{}: {}", k, v));
+    }
+    if lines.is_empty() {
+        return None;
+    }
+    // Mermaid has no escape for double quotes inside labels, only an entity code
+    Some(format!(
+        "\"{}\"",
+        lines.join("<br/>").replace('"', "#quot;")
+    ))
+}
+
+pub fn to_mermaid(graph: &PeerGraph, attrs: &GraphAttributes) -> String {
+    let (nodes, edges) = sorted_graph(graph);
+    // Peer IDs may contain characters Mermaid doesn't allow in node IDs, so number the nodes
+    let index: HashMap<&PeerId, usize> = nodes.iter().enumerate().map(|(i, u)| (*u, i)).collect();
+    let mut out = String::new();
+    out.push_str("flowchart LR\n");
+    for (i, u) in nodes.iter().enumerate() {
+        let label = mermaid_label(Some(short_peer_id(u)), attrs.node(u)).unwrap();
+        out.push_str(&format!("  n{}[{}]\n", i, label));
+    }
+    for (u, v) in edges {
+        let label = mermaid_label(None, attrs.edge(u, v))
+            .map(|l| format!("|{}|", l))
+            .unwrap_or_default();
+        out.push_str(&format!("  n{} -->{} n{}\n", index[u], label, index[v]));
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::graph::import::{parse_dot, parse_graphml};
+    use crate::graph::*;
+
+    fn example() -> (PeerGraph, GraphAttributes) {
+        let peers: Vec<PeerId> = ["peer1_a1b2", "peer2_c3d4", "peer3_e5f6"]
+            .iter()
+            .map(|p| p.to_string())
+            .collect();
+        let graph = line_graph(&peers);
+        let mut attrs = GraphAttributes::new();
+        attrs.set_node(&peers[0], "role", "Gateway");
+        attrs.set_node(&peers[0], "tier", 2usize);
+        attrs.set_node(&peers[1], "role", "Edge \"b\"");
+        attrs.set_edge(&peers[0], &peers[1], "latency_msec", 12.5);
+        (graph, attrs)
+    }
+
+    #[test]
+    fn test_to_dot() {
+        let (graph, attrs) = example();
+        let dot = to_dot(&graph, &attrs);
+        assert!(dot.contains("\"peer1_a1b2\" [label=\"1\", \"role\"=\"Gateway\", \"tier\"=\"2\"];"));
+        assert!(dot.contains("\"role\"=\"Edge \\\"b\\\"\""));
+        assert!(dot.contains("\"peer1_a1b2\" -> \"peer2_c3d4\" [\"latency_msec\"=\"12.5\"];"));
+        assert!(dot.contains("\"peer2_c3d4\" -> \"peer3_e5f6\";"));
+        let parsed = parse_dot(&dot).unwrap();
+        assert_eq!(parsed.nodes.len(), 3);
+        assert_eq!(parsed.edges.len(), 2);
+    }
+
+    #[test]
+    fn test_to_graphml() {
+        let (graph, attrs) = example();
+        let xml = to_graphml(&graph, &attrs);
+        assert!(xml.contains("attr.name=\"tier\" attr.type=\"long\""));
+        assert!(xml.contains("attr.name=\"role\" attr.type=\"string\""));
+        assert!(xml.contains("for=\"edge\" attr.name=\"latency_msec\" attr.type=\"double\""));
+        assert!(xml.contains("Edge &quot;b&quot;"));
+        let parsed = parse_graphml(&xml).unwrap();
+        assert_eq!(parsed.nodes, vec!["peer1_a1b2", "peer2_c3d4", "peer3_e5f6"]);
+        assert_eq!(parsed.edges.len(), 2);
+    }
+
+    #[test]
+    fn test_to_json_and_mermaid() {
+        let (graph, attrs) = example();
+        let doc = to_json_adjacency(&graph, &attrs);
+        assert_eq!(doc["nodes"][0]["id"], "peer1_a1b2");
+        assert_eq!(doc["nodes"][0]["tier"], 2);
+        assert_eq!(doc["adjacency"][0][0]["id"], "peer2_c3d4");
+        assert_eq!(doc["adjacency"][0][0]["latency_msec"], 12.5);
+        assert_eq!(doc["adjacency"][2], json!([]));
+
+        let mmd = to_mermaid(&graph, &attrs);
+        assert!(mmd.contains("  n0[\"1<br/>role: Gateway<br/>tier: 2\"]\n"));
+        assert!(mmd.contains("  n1[\"2<br/>role: Edge #quot;b#quot;\"]\n"));
+        assert!(mmd.contains("  n0 -->|\"latency_msec: 12.5\"| n1\n"));
+        assert!(mmd.contains("  n1 --> n2\n"));
+    }
+
+    #[test]
+    fn test_link_attributes() {
+        let (graph, _) = example();
+        let ids = ["peer1_a1b2", "peer2_c3d4", "peer3_e5f6"];
+        let groups = ["a", "a", "b"];
+        let roles = [PeerRole::Gateway, PeerRole::Edge, PeerRole::Edge];
+        let peers: Vec<Peer> = (0..3)
+            .map(|i| Peer {
+                peer_id: ids[i].to_string(),
+                peer_ip_addr: "10.0.0.1".to_string(),
+                peer_port: 4000 + i as u16,
+                state: PeerState::Init,
+                position: None,
+                group: Some(groups[i].to_string()),
+                role: Some(roles[i]),
+                tier: None,
+            })
+            .collect();
+        let mut attrs = GraphAttributes::from_peers(&peers);
+        attrs.set_links(&graph, &peers);
+        let dot = to_dot(&graph, &attrs);
+        assert!(dot.contains(
+            "\"peer1_a1b2\" -> \"peer2_c3d4\" [\"cross_group\"=\"false\", \"roles\"=\"Gateway-Edge\"];"
+        ));
+        let xml = to_graphml(&graph, &attrs);
+        assert!(xml.contains("for=\"edge\" attr.name=\"cross_group\" attr.type=\"boolean\""));
+        let doc = to_json_adjacency(&graph, &attrs);
+        assert_eq!(doc["adjacency"][1][0]["cross_group"], true);
+        assert_eq!(doc["adjacency"][1][0]["roles"], "Edge-Edge");
+        let mmd = to_mermaid(&graph, &attrs);
+        assert!(mmd.contains("  n1 -->|\"cross_group: true<br/>roles: Edge-Edge\"| n2\n"));
+    }
+
+    #[test]
+    fn test_pin_positions() {
+        let (graph, _) = example();
+        let mut attrs = GraphAttributes::new();
+        let positions = HashMap::from([
+            ("peer1_a1b2".to_string(), Position { x: 0.0, y: 0.0 }),
+            ("peer2_c3d4".to_string(), Position { x: 10.0, y: 5.0 }),
+        ]);
+        attrs.pin_positions(&positions);
+        let dot = to_dot(&graph, &attrs);
+        assert!(dot.starts_with("digraph G {\n  \"layout\"=\"neato\";\n"));
+        assert!(dot.contains("\"peer2_c3d4\" [label=\"2\", \"pos\"=\"101.8,-50.9!\"];"));
+        assert!(dot.contains("\"peer3_e5f6\" [label=\"3\"];"));
+        assert_eq!(parse_dot(&dot).unwrap().edges.len(), 2);
+        let xml = to_graphml(&graph, &attrs);
+        assert!(xml.contains("for=\"graph\" attr.name=\"layout\" attr.type=\"string\""));
+        let doc = to_json_adjacency(&graph, &attrs);
+        assert_eq!(doc["graph"]["layout"], "neato");
+    }
+}
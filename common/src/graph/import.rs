@@ -0,0 +1,438 @@
+use serde_json::Value;
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+
+use crate::types::*;
+
+// Loading connection graphs from files, e.g. topologies captured in the field. Files describe
+// edges between arbitrary node labels; map_labels() then assigns those labels to the peers that
+// actually joined the test. As with generated graphs, an edge a -> b means a calls connect().
+//
+// Supported formats, chosen by file extension:
+// - DOT (.dot, .gv): node and edge statements, `->` or `--`. Attributes are ignored.
+// - JSON (.json): either a bare edge list, [["a", "b"], ...], or an object
+//   {"nodes": ["a", ...], "edges": [["a", "b"], ...]}, where edges may also be
+//   {"source": "a", "target": "b"} objects and nodes {"id": "a"} objects.
+// - GraphML (.graphml, .xml): <node id=".."> and <edge source=".." target=".."> elements.
+
+// A graph read from a file, before its node labels are mapped to peers
+#[derive(Debug, Default)]
+pub struct LabeledGraph {
+    pub nodes: Vec<String>,
+    pub edges: Vec<(String, String)>,
+}
+
+impl LabeledGraph {
+    fn add_node(&mut self, label: &str) {
+        if !self.nodes.iter().any(|n| n == label) {
+            self.nodes.push(label.to_string());
+        }
+    }
+
+    fn add_edge(&mut self, u: &str, v: &str) {
+        self.add_node(u);
+        self.add_node(v);
+        self.edges.push((u.to_string(), v.to_string()));
+    }
+}
+
+pub fn load_graph_file(path: &Path) -> Result<LabeledGraph, Box<dyn Error>> {
+    let text = fs::read_to_string(path)
+        .map_err(|e| format!("Cannot read graph file {}: {}", path.display(), e))?;
+    let ext = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .unwrap_or("")
+        .to_lowercase();
+    let graph = match ext.as_str() {
+        "dot" | "gv" => parse_dot(&text),
+        "json" => parse_json(&text),
+        "graphml" | "xml" => parse_graphml(&text),
+        _ => Err(format!(
+            "Unknown graph file extension '{}', expected .dot, .json or .graphml",
+            ext
+        )
+        .into()),
+    }
+    .map_err(|e| format!("Cannot parse graph file {}: {}", path.display(), e))?;
+    if graph.nodes.is_empty() {
+        return Err(format!("Graph file {} has no nodes", path.display()).into());
+    }
+    Ok(graph)
+}
+
+// Sort key that orders numeric labels by value, e.g. "2" before "10"
+fn label_key(label: &str) -> (Option<u64>, &str) {
+    (label.parse().ok(), label)
+}
+
+// Assign labels to peers, as in map_labels(). With fewer labels than peers, the peers left over
+// get none.
+pub(crate) fn label_mapping<'a>(
+    labels: &'a [String],
+    peers: &'a [PeerId],
+) -> HashMap<&'a str, &'a PeerId> {
+    let by_short_id: HashMap<String, &PeerId> =
+        peers.iter().map(|p| (short_peer_id(p), p)).collect();
+    let mut mapping: HashMap<&str, &PeerId> = HashMap::new();
+    if by_short_id.len() == peers.len() && labels.iter().all(|n| by_short_id.contains_key(n)) {
+        for n in labels {
+            mapping.insert(n, by_short_id[n]);
+        }
+    } else {
+        let mut labels: Vec<&String> = labels.iter().collect();
+        labels.sort_by(|a, b| label_key(a).cmp(&label_key(b)));
+        let mut sorted_peers: Vec<(String, &PeerId)> =
+            peers.iter().map(|p| (short_peer_id(p), p)).collect();
+        sorted_peers.sort_by(|a, b| label_key(&a.0).cmp(&label_key(&b.0)).then(a.1.cmp(b.1)));
+        for (label, (_, peer)) in labels.into_iter().zip(sorted_peers) {
+            mapping.insert(label, peer);
+        }
+    }
+    mapping
+}
+
+// Assign node labels to peers. If every label is the short ID of a peer ("3" for peer3_..),
+// use that peer; otherwise pair labels and peers in sorted order.
+pub fn map_labels(graph: &LabeledGraph, peers: &[PeerId]) -> Result<PeerGraph, Box<dyn Error>> {
+    if graph.nodes.len() != peers.len() {
+        return Err(format!(
+            "Graph file has {} nodes, but {} peers joined",
+            graph.nodes.len(),
+            peers.len()
+        )
+        .into());
+    }
+    let mapping = label_mapping(&graph.nodes, peers);
+    let mut pgraph = PeerGraph::new();
+    for n in &graph.nodes {
+        pgraph.add_vertex(mapping[n.as_str()]);
+    }
+    for (u, v) in &graph.edges {
+        let (u, v) = (mapping[u.as_str()], mapping[v.as_str()]);
+        if u != v && !pgraph.has_edge(u, v) {
+            pgraph.add_edge(u, v);
+        }
+    }
+    Ok(pgraph)
+}
+
+fn dot_tokens(text: &str) -> Result<Vec<String>, Box<dyn Error>> {
+    let mut tokens = Vec::new();
+    let chars: Vec<char> = text.chars().collect();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        let next = chars.get(i + 1).copied();
+        if c.is_whitespace() {
+            i += 1;
+        } else if c == '/' && next == Some('/') || c == '#' {
+            while i < chars.len() && chars[i] != '\n' {
+                i += 1;
+            }
+        } else if c == '/' && next == Some('*') {
+            i += 2;
+            while i < chars.len() && !(chars[i] == '*' && chars.get(i + 1) == Some(&'/')) {
+                i += 1;
+            }
+            i += 2;
+        } else if c == '-' && (next == Some('>') || next == Some('-')) {
+            tokens.push("->".to_string());
+            i += 2;
+        } else if "{}[];,=:".contains(c) {
+            tokens.push(c.to_string());
+            i += 1;
+        } else if c == '"' {
+            let mut s = String::new();
+            i += 1;
+            while i < chars.len() && chars[i] != '"' {
+                if chars[i] == '\\' && i + 1 < chars.len() {
+                    i += 1;
+                }
+                s.push(chars[i]);
+                i += 1;
+            }
+            if i >= chars.len() {
+                return Err("unterminated string".into());
+            }
+            // Mark quoted IDs so they are never taken for keywords
+            tokens.push(format!("\"{}", s));
+            i += 1;
+        } else if c.is_alphanumeric() || c == '_' || c == '.' || c == '-' {
+            let start = i;
+            while i < chars.len() && (chars[i].is_alphanumeric() || "_.-".contains(chars[i])) {
+                if chars[i] == '-' && i > start {
+                    break;
+                }
+                i += 1;
+            }
+            tokens.push(chars[start..i].iter().collect());
+        } else {
+            return Err(format!("unexpected character '{}'", c).into());
+        }
+    }
+    Ok(tokens)
+}
+
+pub fn parse_dot(text: &str) -> Result<LabeledGraph, Box<dyn Error>> {
+    let keywords = ["strict", "graph", "digraph", "subgraph", "node", "edge"];
+    let tokens = dot_tokens(text)?;
+    let mut graph = LabeledGraph::default();
+    let mut prev: Option<String> = None;
+    let mut pending_edge = false;
+    let mut i = 0;
+    while i < tokens.len() {
+        let t = tokens[i].as_str();
+        match t {
+            "->" => {
+                if prev.is_none() {
+                    return Err("edge without a source node".into());
+                }
+                pending_edge = true;
+            }
+            "[" => {
+                // Skip attribute list
+                while i < tokens.len() && tokens[i] != "]" {
+                    i += 1;
+                }
+            }
+            ":" => {
+                // Skip port
+                i += 1;
+            }
+            "{" if pending_edge => return Err("edges to subgraphs are not supported".into()),
+            "{" | "}" | ";" | "," | "=" => {
+                prev = None;
+            }
+            _ if keywords.contains(&t.to_lowercase().as_str()) => {
+                prev = None;
+                // Skip the graph's name, if any
+                let kw = t.to_lowercase();
+                let named = kw == "graph" || kw == "digraph" || kw == "subgraph";
+                if named && tokens.get(i + 1).map_or(false, |n| n != "{" && n != "[") {
+                    i += 1;
+                }
+            }
+            _ => {
+                let id = t.strip_prefix('"').unwrap_or(t);
+                if tokens.get(i + 1).map_or(false, |n| n == "=") {
+                    // Graph attribute assignment, ID = ID
+                    i += 2;
+                } else {
+                    if pending_edge {
+                        graph.add_edge(prev.as_ref().unwrap(), id);
+                        pending_edge = false;
+                    } else {
+                        graph.add_node(id);
+                    }
+                    prev = Some(id.to_string());
+                }
+            }
+        }
+        i += 1;
+    }
+    if pending_edge {
+        return Err("edge without a target node".into());
+    }
+    Ok(graph)
+}
+
+fn json_label(v: &Value) -> Result<String, Box<dyn Error>> {
+    match v {
+        Value::String(s) => Ok(s.clone()),
+        Value::Number(n) => Ok(n.to_string()),
+        Value::Object(o) => match o.get("id") {
+            Some(id) => json_label(id),
+            None => Err("node object without an \"id\"".into()),
+        },
+        _ => Err(format!("invalid node label {}", v).into()),
+    }
+}
+
+pub fn parse_json(text: &str) -> Result<LabeledGraph, Box<dyn Error>> {
+    let doc: Value = serde_json::from_str(text)?;
+    let mut graph = LabeledGraph::default();
+    let edges = match &doc {
+        Value::Array(edges) => edges,
+        Value::Object(o) => {
+            if let Some(nodes) = o.get("nodes") {
+                for n in nodes.as_array().ok_or("\"nodes\" must be an array")? {
+                    graph.add_node(&json_label(n)?);
+                }
+            }
+            o.get("edges")
+                .and_then(|e| e.as_array())
+                .ok_or("expected an \"edges\" array")?
+        }
+        _ => return Err("expected an edge list or an object with \"edges\"".into()),
+    };
+    for e in edges {
+        let (u, v) = match e {
+            Value::Array(pair) if pair.len() == 2 => (json_label(&pair[0])?, json_label(&pair[1])?),
+            Value::Object(o) => match (o.get("source"), o.get("target")) {
+                (Some(u), Some(v)) => (json_label(u)?, json_label(v)?),
+                _ => return Err(format!("edge {} needs \"source\" and \"target\"", e).into()),
+            },
+            _ => return Err(format!("invalid edge {}", e).into()),
+        };
+        graph.add_edge(&u, &v);
+    }
+    Ok(graph)
+}
+
+// Value of attribute `name` in the text of an XML start tag
+fn xml_attr(tag: &str, name: &str) -> Option<String> {
+    let mut rest = tag;
+    while let Some(pos) = rest.find(name) {
+        let before = rest[..pos].chars().last();
+        let after = rest[pos + name.len()..].trim_start();
+        rest = &rest[pos + name.len()..];
+        if !before.map_or(false, char::is_whitespace) || !after.starts_with('=') {
+            continue;
+        }
+        let value = after[1..].trim_start();
+        let quote = value.chars().next()?;
+        if quote != '"' && quote != '\'' {
+            return None;
+        }
+        let end = value[1..].find(quote)?;
+        return Some(value[1..end + 1].to_string());
+    }
+    None
+}
+
+pub fn parse_graphml(text: &str) -> Result<LabeledGraph, Box<dyn Error>> {
+    let mut graph = LabeledGraph::default();
+    let mut nodes = HashSet::new();
+    let mut rest = text;
+    while let Some(start) = rest.find('<') {
+        rest = &rest[start + 1..];
+        if rest.starts_with("!--") {
+            let end = rest.find("-->").ok_or("unterminated comment")?;
+            rest = &rest[end + 3..];
+            continue;
+        }
+        let end = rest.find('>').ok_or("unterminated tag")?;
+        let tag = &rest[..end];
+        rest = &rest[end + 1..];
+        let name = tag
+            .split(|c: char| c.is_whitespace() || c == '/')
+            .next()
+            .unwrap_or("");
+        match name {
+            "node" => {
+                let id = xml_attr(tag, "id").ok_or("<node> without an id")?;
+                nodes.insert(id.clone());
+                graph.add_node(&id);
+            }
+            "edge" => {
+                let u = xml_attr(tag, "source").ok_or("<edge> without a source")?;
+                let v = xml_attr(tag, "target").ok_or("<edge> without a target")?;
+                graph.add_edge(&u, &v);
+            }
+            _ => {}
+        }
+    }
+    if let Some((u, v)) = graph
+        .edges
+        .iter()
+        .find(|(u, v)| !nodes.contains(u) || !nodes.contains(v))
+    {
+        return Err(format!("edge {} -> {} refers to an undeclared node", u, v).into());
+    }
+    Ok(graph)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_dot() {
+        let text = r#"
+            // field exercise 3
+            digraph "ex3" {
+                rankdir=LR;
+                node [shape=circle];
+                a -> b -> c [label="radio"];
+                "d e" -- a
+                f;
+                /* g -> h */
+            }
+        "#;
+        let g = parse_dot(text).unwrap();
+        assert_eq!(g.nodes, vec!["a", "b", "c", "d e", "f"]);
+        assert_eq!(
+            g.edges,
+            vec![
+                ("a".to_string(), "b".to_string()),
+                ("b".to_string(), "c".to_string()),
+                ("d e".to_string(), "a".to_string()),
+            ]
+        );
+        assert!(parse_dot("digraph { a -> }").is_err());
+        assert!(parse_dot("digraph { a -> { b c } }").is_err());
+    }
+
+    #[test]
+    fn test_parse_json() {
+        let g = parse_json(r#"[["a", "b"], [1, 2]]"#).unwrap();
+        assert_eq!(g.nodes, vec!["a", "b", "1", "2"]);
+        assert_eq!(g.edges.len(), 2);
+
+        let g = parse_json(
+            r#"{"nodes": [{"id": "x"}, "y", "z"],
+                "edges": [{"source": "x", "target": "y"}, ["y", "z"]]}"#,
+        )
+        .unwrap();
+        assert_eq!(g.nodes, vec!["x", "y", "z"]);
+        assert_eq!(g.edges[0], ("x".to_string(), "y".to_string()));
+        assert!(parse_json(r#"{"nodes": []}"#).is_err());
+    }
+
+    #[test]
+    fn test_parse_graphml() {
+        let text = r#"<?xml version="1.0" encoding="UTF-8"?>
+            <graphml xmlns="http://graphml.graphdrawing.org/xmlns">
+              <key id="d0" for="edge" attr.name="weight" attr.type="double"/>
+              <graph id="G" edgedefault="undirected">
+                <node id="n0"/>
+                <node id='n1'></node>
+                <!-- <node id="n9"/> -->
+                <node id="n2"/>
+                <edge id="e0" source="n0" target="n1"><data key="d0">1.0</data></edge>
+                <edge source="n1" target="n2"/>
+              </graph>
+            </graphml>"#;
+        let g = parse_graphml(text).unwrap();
+        assert_eq!(g.nodes, vec!["n0", "n1", "n2"]);
+        assert_eq!(g.edges.len(), 2);
+        assert!(
+            parse_graphml(r#"<graph><node id="a"/><edge source="a" target="b"/></graph>"#).is_err()
+        );
+    }
+
+    #[test]
+    fn test_map_labels() {
+        let peers: Vec<PeerId> = ["peer0_aa", "peer1_bb", "peer2_cc", "peer10_dd"]
+            .iter()
+            .map(|p| p.to_string())
+            .collect();
+        // Labels match short IDs
+        let g = parse_json(r#"[["10", "0"], ["0", "1"], ["1", "2"]]"#).unwrap();
+        let pg = map_labels(&g, &peers).unwrap();
+        assert!(pg.nmap["peer10_dd"].contains("peer0_aa"));
+        assert!(pg.nmap["peer1_bb"].contains("peer2_cc"));
+
+        // Arbitrary labels: sorted order
+        let g = parse_json(r#"[["w", "x"], ["x", "y"], ["y", "z"]]"#).unwrap();
+        let pg = map_labels(&g, &peers).unwrap();
+        assert!(pg.nmap["peer0_aa"].contains("peer1_bb"));
+        assert!(pg.nmap["peer2_cc"].contains("peer10_dd"));
+
+        let g = parse_json(r#"[["a", "b"]]"#).unwrap();
+        assert!(map_labels(&g, &peers).is_err());
+    }
+}
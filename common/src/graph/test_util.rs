@@ -0,0 +1,7 @@
+use crate::types::PeerId;
+
+// Helpers shared by the unit tests of the graph and scenario modules
+
+pub fn to_peer_ids_vec(ids: std::ops::Range<usize>) -> Vec<PeerId> {
+    ids.into_iter().map(|x| x.to_string()).collect()
+}
@@ -0,0 +1,272 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+
+use super::import::label_mapping;
+use crate::types::*;
+
+// Replaying contact traces, e.g. contacts logged between radios during a field exercise. A trace
+// lists when pairs of nodes were in contact; trace_epochs() turns it into topology epochs, with
+// node labels assigned to peers as for graph files (see map_labels()). The first node of each
+// contact dials.
+//
+// Supported formats, told apart by their contents:
+// - CSV: `t_start,t_end,node_a,node_b` per contact, times in seconds. An optional header line is
+//   skipped.
+// - The ONE simulator's connection events: `time CONN node_a node_b up|down`. Other events are
+//   skipped, and contacts still up at the end of the trace last forever.
+// Blank lines and lines starting with '#' are ignored in either format.
+
+// When two nodes were in contact, in msec of trace time
+#[derive(Clone, Debug, PartialEq)]
+pub struct Contact {
+    pub start_msec: u64,
+    pub end_msec: u64,
+    pub a: String,
+    pub b: String,
+}
+
+#[derive(Debug, Default)]
+pub struct ContactTrace {
+    pub nodes: Vec<String>,
+    pub contacts: Vec<Contact>,
+}
+
+impl ContactTrace {
+    fn add_contact(&mut self, start_msec: u64, end_msec: u64, a: &str, b: &str) {
+        for n in [a, b] {
+            if !self.nodes.iter().any(|x| x == n) {
+                self.nodes.push(n.to_string());
+            }
+        }
+        self.contacts.push(Contact {
+            start_msec,
+            end_msec,
+            a: a.to_string(),
+            b: b.to_string(),
+        });
+    }
+}
+
+pub fn load_contact_trace(path: &Path) -> Result<ContactTrace, Box<dyn Error>> {
+    let text = fs::read_to_string(path)
+        .map_err(|e| format!("Cannot read contact trace {}: {}", path.display(), e))?;
+    let is_one = data_lines(&text).any(|(_, l)| l.split_whitespace().nth(1) == Some("CONN"));
+    let trace = if is_one {
+        parse_one(&text)
+    } else {
+        parse_csv(&text)
+    }
+    .map_err(|e| format!("Cannot parse contact trace {}: {}", path.display(), e))?;
+    if trace.contacts.is_empty() {
+        return Err(format!("Contact trace {} has no contacts", path.display()).into());
+    }
+    Ok(trace)
+}
+
+// (line number, trimmed line) of lines that aren't blank or comments
+fn data_lines(text: &str) -> impl Iterator<Item = (usize, &str)> {
+    text.lines()
+        .enumerate()
+        .map(|(i, l)| (i + 1, l.trim()))
+        .filter(|(_, l)| !l.is_empty() && !l.starts_with('#'))
+}
+
+fn parse_msec(s: &str, line: usize) -> Result<u64, Box<dyn Error>> {
+    match s.trim().parse::<f64>() {
+        Ok(sec) if sec >= 0.0 && sec.is_finite() => Ok((sec * 1000.0).round() as u64),
+        _ => Err(format!("line {}: bad time '{}'", line, s).into()),
+    }
+}
+
+fn parse_csv(text: &str) -> Result<ContactTrace, Box<dyn Error>> {
+    let mut trace = ContactTrace::default();
+    for (n, (line, l)) in data_lines(text).enumerate() {
+        let fields: Vec<&str> = l.split(',').map(|f| f.trim()).collect();
+        if fields.len() != 4 {
+            return Err(format!("line {}: expected t_start,t_end,node_a,node_b", line).into());
+        }
+        if n == 0 && fields[0].parse::<f64>().is_err() {
+            continue; // header
+        }
+        let (start, end) = (parse_msec(fields[0], line)?, parse_msec(fields[1], line)?);
+        if start > end {
+            return Err(format!("line {}: contact ends before it starts", line).into());
+        }
+        trace.add_contact(start, end, fields[2], fields[3]);
+    }
+    Ok(trace)
+}
+
+fn parse_one(text: &str) -> Result<ContactTrace, Box<dyn Error>> {
+    let mut trace = ContactTrace::default();
+    // Contacts that are up: (unordered pair) -> (start msec, dialer, listener)
+    let mut up: HashMap<(&str, &str), (u64, &str, &str)> = HashMap::new();
+    let mut last_msec = 0;
+    for (line, l) in data_lines(text) {
+        let fields: Vec<&str> = l.split_whitespace().collect();
+        if fields.get(1) != Some(&"CONN") {
+            continue;
+        }
+        if fields.len() != 5 {
+            return Err(format!("line {}: expected time CONN node_a node_b up|down", line).into());
+        }
+        let t = parse_msec(fields[0], line)?;
+        if t < last_msec {
+            return Err(format!("line {}: events out of time order", line).into());
+        }
+        last_msec = t;
+        let (a, b) = (fields[2], fields[3]);
+        let key = if a < b { (a, b) } else { (b, a) };
+        match fields[4] {
+            "up" => {
+                // Repeated ups keep the first
+                up.entry(key).or_insert((t, a, b));
+            }
+            "down" => {
+                let (start, a, b) = up
+                    .remove(&key)
+                    .ok_or_else(|| format!("line {}: {} and {} were not up", line, a, b))?;
+                trace.add_contact(start, t, a, b);
+            }
+            other => {
+                return Err(format!("line {}: expected up or down, not '{}'", line, other).into())
+            }
+        }
+    }
+    let mut open: Vec<(u64, &str, &str)> = up.into_values().collect();
+    open.sort();
+    for (start, a, b) in open {
+        trace.add_contact(start, u64::MAX, a, b);
+    }
+    Ok(trace)
+}
+
+// Topology epochs replaying the trace from trace time start_msec for duration_msec: a new epoch
+// wherever the set of contacts changes. Peers without a trace node have no links.
+pub fn trace_epochs(
+    trace: &ContactTrace,
+    peers: &[PeerId],
+    start_msec: u64,
+    duration_msec: u64,
+) -> Result<Vec<TopologyEpoch>, Box<dyn Error>> {
+    if trace.nodes.len() > peers.len() {
+        return Err(format!(
+            "Contact trace has {} nodes, but only {} peers joined",
+            trace.nodes.len(),
+            peers.len()
+        )
+        .into());
+    }
+    let mapping = label_mapping(&trace.nodes, peers);
+    // Contacts during the test, in msec after the start
+    let contacts: Vec<(u64, u64, &PeerId, &PeerId)> = trace
+        .contacts
+        .iter()
+        .filter(|c| c.end_msec > start_msec && c.start_msec < start_msec + duration_msec.max(1))
+        .map(|c| {
+            (
+                c.start_msec.saturating_sub(start_msec),
+                c.end_msec - start_msec,
+                mapping[c.a.as_str()],
+                mapping[c.b.as_str()],
+            )
+        })
+        .collect();
+    // Always an epoch at 0, even for a zero-length test
+    let mut times: Vec<u64> = std::iter::once(0)
+        .chain(
+            contacts
+                .iter()
+                .flat_map(|c| [c.0, c.1])
+                .filter(|t| *t < duration_msec),
+        )
+        .collect();
+    times.sort_unstable();
+    times.dedup();
+
+    let mut epochs: Vec<TopologyEpoch> = Vec::new();
+    for t in times {
+        let mut connections = PeerGraph::new();
+        for p in peers {
+            connections.add_vertex(p);
+        }
+        for (start, end, a, b) in &contacts {
+            if *start <= t && t < *end && a != b && !connections.has_edge(a, b) {
+                connections.add_edge(a, b);
+            }
+        }
+        if let Some(last) = epochs.last() {
+            if last.connections.nmap == connections.nmap {
+                continue;
+            }
+        }
+        epochs.push(TopologyEpoch {
+            offset_msec: t,
+            connections,
+        });
+    }
+    Ok(epochs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_contact_traces() {
+        let csv = "t_start,t_end,node_a,node_b\n0,10,a,b\n\n# lost radio\n5.5, 20, b, c\n";
+        let t = parse_csv(csv).unwrap();
+        assert_eq!(t.nodes, vec!["a", "b", "c"]);
+        assert_eq!(t.contacts[1].start_msec, 5500);
+        assert_eq!(t.contacts[1].end_msec, 20_000);
+        assert!(parse_csv("0,10,a\n").is_err());
+        assert!(parse_csv("10,0,a,b\n").is_err());
+
+        let one = "0 CONN 1 2 up\n2.5 C M1 1 2\n3 CONN 2 3 up\n4 CONN 2 1 down\n4 CONN 1 2 up\n";
+        let t = parse_one(one).unwrap();
+        assert_eq!(t.contacts.len(), 3);
+        assert_eq!(
+            t.contacts[0],
+            Contact {
+                start_msec: 0,
+                end_msec: 4000,
+                a: "1".to_string(),
+                b: "2".to_string(),
+            }
+        );
+        assert_eq!(t.contacts[1].end_msec, u64::MAX);
+        assert!(parse_one("0 CONN 1 2 down\n").is_err());
+        assert!(parse_one("5 CONN 1 2 up\n4 CONN 1 2 down\n").is_err());
+    }
+
+    #[test]
+    fn test_trace_epochs() {
+        let peers: Vec<PeerId> = ["peer1_aa", "peer2_bb", "peer3_cc", "peer4_dd"]
+            .iter()
+            .map(|p| p.to_string())
+            .collect();
+        let one = "0 CONN 1 2 up\n10 CONN 2 3 up\n20 CONN 1 2 down\n30 CONN 3 2 up\n";
+        let trace = parse_one(one).unwrap();
+        let epochs = trace_epochs(&trace, &peers, 5000, 60_000).unwrap();
+        let offsets: Vec<u64> = epochs.iter().map(|e| e.offset_msec).collect();
+        assert_eq!(offsets, vec![0, 5000, 15_000]);
+        let (p1, p2, p3) = (&peers[0], &peers[1], &peers[2]);
+        assert!(epochs[0].connections.nmap[p1].contains(p2));
+        assert!(!epochs[0].connections.has_edge(p2, p3));
+        assert!(epochs[1].connections.nmap[p2].contains(p3));
+        assert!(!epochs[2].connections.has_edge(p1, p2));
+        for e in &epochs {
+            assert_eq!(e.connections.nmap.len(), 4);
+            assert_eq!(e.connections.degree(&peers[3]), 0);
+        }
+
+        let epochs = trace_epochs(&trace, &peers, 0, 0).unwrap();
+        assert_eq!(epochs.len(), 1);
+        assert!(epochs[0].connections.nmap[p1].contains(p2));
+
+        let csv = "0,100,x,y\n0,100,y,z\n0,100,z,w\n0,100,w,v\n";
+        assert!(trace_epochs(&parse_csv(csv).unwrap(), &peers, 0, 60_000).is_err());
+    }
+}
@@ -3,3 +3,6 @@ pub mod types;
 pub mod util;
 pub mod minheap;
 pub mod graph;
+pub mod validate;
+pub mod mobility;
+pub mod scenario;
@@ -0,0 +1,181 @@
+use std::collections::HashMap;
+
+use rand::Rng;
+
+use crate::graph::{proximity_graph, random_position};
+use crate::types::{PeerId, Position, TopologyEpoch};
+
+// Random waypoint mobility model: each peer starts at a random point in an area_size x area_size
+// square, moves in a straight line to another random point at a speed drawn uniformly from
+// [min_speed, max_speed], waits there for pause_msec, then picks its next waypoint.
+#[derive(Clone, Debug)]
+pub struct RandomWaypoint {
+    pub area_size: f64,
+    // Area units per second
+    pub min_speed: f64,
+    pub max_speed: f64,
+    pub pause_msec: u64,
+}
+
+struct Walker {
+    position: Position,
+    waypoint: Position,
+    speed: f64,
+    pause_left_msec: u64,
+}
+
+impl RandomWaypoint {
+    fn new_walker<R: Rng>(&self, rng: &mut R) -> Walker {
+        Walker {
+            position: random_position(rng, self.area_size),
+            waypoint: random_position(rng, self.area_size),
+            speed: rng.gen_range(self.min_speed..=self.max_speed),
+            pause_left_msec: 0,
+        }
+    }
+
+    // Move w along its path for dt_msec, pausing and picking new waypoints as it reaches them
+    fn advance<R: Rng>(&self, rng: &mut R, w: &mut Walker, dt_msec: u64) {
+        let mut left = dt_msec as f64;
+        while left > 0.0 {
+            if w.pause_left_msec > 0 {
+                let pause = w.pause_left_msec.min(left as u64);
+                w.pause_left_msec -= pause;
+                left -= pause as f64;
+                if w.pause_left_msec > 0 {
+                    break;
+                }
+                w.waypoint = random_position(rng, self.area_size);
+                w.speed = rng.gen_range(self.min_speed..=self.max_speed);
+                continue;
+            }
+            let dist = w.position.distance(&w.waypoint);
+            let reach_msec = dist / w.speed * 1000.0;
+            if reach_msec > left {
+                let f = left / reach_msec;
+                w.position.x += (w.waypoint.x - w.position.x) * f;
+                w.position.y += (w.waypoint.y - w.position.y) * f;
+                break;
+            }
+            w.position = w.waypoint;
+            left -= reach_msec;
+            // A zero pause still has to pick a new waypoint
+            w.pause_left_msec = self.pause_msec.max(1);
+        }
+    }
+
+    // Positions of every peer at times 0, tick_msec, 2 * tick_msec, ... before duration_msec
+    pub fn sample<R: Rng>(
+        &self,
+        rng: &mut R,
+        peers: &[PeerId],
+        tick_msec: u64,
+        duration_msec: u64,
+    ) -> Vec<HashMap<PeerId, Position>> {
+        assert!(self.area_size > 0.0 && tick_msec > 0);
+        assert!(0.0 < self.min_speed && self.min_speed <= self.max_speed);
+        let mut walkers: Vec<Walker> = peers.iter().map(|_| self.new_walker(rng)).collect();
+        let mut samples = Vec::new();
+        let mut t = 0;
+        while t < duration_msec.max(1) {
+            if t > 0 {
+                for w in walkers.iter_mut() {
+                    self.advance(rng, w, tick_msec);
+                }
+            }
+            samples.push(
+                peers
+                    .iter()
+                    .zip(&walkers)
+                    .map(|(p, w)| (p.clone(), w.position))
+                    .collect(),
+            );
+            t += tick_msec;
+        }
+        samples
+    }
+}
+
+// Proximity graph of each sample from RandomWaypoint::sample, as topology epochs. Samples whose
+// graph is the same as the previous epoch's are skipped.
+pub fn mobility_epochs(
+    peers: &[PeerId],
+    samples: &[HashMap<PeerId, Position>],
+    tick_msec: u64,
+    range: f64,
+) -> Vec<TopologyEpoch> {
+    let mut epochs: Vec<TopologyEpoch> = Vec::new();
+    for (i, positions) in samples.iter().enumerate() {
+        let connections = proximity_graph(peers, positions, range);
+        if let Some(last) = epochs.last() {
+            if last.connections.nmap == connections.nmap {
+                continue;
+            }
+        }
+        epochs.push(TopologyEpoch {
+            offset_msec: i as u64 * tick_msec,
+            connections,
+        });
+    }
+    epochs
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    use super::*;
+    use crate::graph::test_util::to_peer_ids_vec;
+
+    fn model() -> RandomWaypoint {
+        RandomWaypoint {
+            area_size: 1000.0,
+            min_speed: 5.0,
+            max_speed: 20.0,
+            pause_msec: 2000,
+        }
+    }
+
+    #[test]
+    fn test_random_waypoint() {
+        let peers = to_peer_ids_vec(0..20);
+        let samples = model().sample(&mut StdRng::seed_from_u64(3), &peers, 1000, 60_000);
+        assert_eq!(samples.len(), 60);
+        for (prev, next) in samples.iter().zip(&samples[1..]) {
+            for p in &peers {
+                let pos = next[p];
+                assert!((0.0..=1000.0).contains(&pos.x) && (0.0..=1000.0).contains(&pos.y));
+                // No faster than max_speed over a one second tick
+                assert!(prev[p].distance(&pos) <= 20.0 + 1e-9);
+            }
+        }
+        assert!(peers
+            .iter()
+            .any(|p| samples[0][p].distance(&samples[59][p]) > 0.0));
+        let again = model().sample(&mut StdRng::seed_from_u64(3), &peers, 1000, 60_000);
+        assert_eq!(samples, again);
+    }
+
+    #[test]
+    fn test_mobility_epochs() {
+        let peers = to_peer_ids_vec(0..30);
+        let samples = model().sample(&mut StdRng::seed_from_u64(5), &peers, 1000, 120_000);
+        let epochs = mobility_epochs(&peers, &samples, 1000, 250.0);
+        assert_eq!(epochs[0].offset_msec, 0);
+        assert!(epochs.len() > 1);
+        for (prev, next) in epochs.iter().zip(&epochs[1..]) {
+            assert!(next.offset_msec > prev.offset_msec);
+            assert_eq!(next.offset_msec % 1000, 0);
+            assert_ne!(prev.connections.nmap, next.connections.nmap);
+        }
+        for e in &epochs {
+            let i = (e.offset_msec / 1000) as usize;
+            for (u, neighbors) in &e.connections.nmap {
+                for v in neighbors {
+                    assert!(samples[i][u].distance(&samples[i][v]) <= 250.0);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,508 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+
+use rand::seq::SliceRandom;
+use rand::Rng;
+
+use crate::types::*;
+
+// Copy of graph without the links between peers in different partitions
+pub fn split_graph(graph: &PeerGraph, partitions: &[Vec<PeerId>]) -> PeerGraph {
+    let part: HashMap<&PeerId, usize> = partitions
+        .iter()
+        .enumerate()
+        .flat_map(|(i, p)| p.iter().map(move |u| (u, i)))
+        .collect();
+    let mut split = PeerGraph::new();
+    for (u, neighbors) in &graph.nmap {
+        split.add_vertex(u);
+        for v in neighbors {
+            if part.get(u) == part.get(v) {
+                split.add_edge(u, v);
+            }
+        }
+    }
+    split
+}
+
+// Epochs that follow the given ones, or the fixed connections if there are none, except that
+// links between partitions are down during the split. Epochs are added at the split and heal
+// times, and any that end up the same as the one before are dropped.
+pub fn partition_epochs(
+    connections: &PeerGraph,
+    epochs: &[TopologyEpoch],
+    schedule: &PartitionSchedule,
+) -> Vec<TopologyEpoch> {
+    let base = [TopologyEpoch {
+        offset_msec: 0,
+        connections: connections.clone(),
+    }];
+    let epochs = if epochs.is_empty() { &base[..] } else { epochs };
+    let mut offsets: Vec<u64> = epochs.iter().map(|e| e.offset_msec).collect();
+    offsets.extend([schedule.split_offset_msec, schedule.heal_offset_msec]);
+    offsets.sort_unstable();
+    offsets.dedup();
+
+    let mut out: Vec<TopologyEpoch> = Vec::new();
+    for offset in offsets {
+        let current = &epochs[epochs
+            .iter()
+            .rposition(|e| e.offset_msec <= offset)
+            .unwrap_or(0)];
+        let split = schedule.split_offset_msec <= offset && offset < schedule.heal_offset_msec;
+        let connections = if split {
+            split_graph(&current.connections, &schedule.partitions)
+        } else {
+            current.connections.clone()
+        };
+        if let Some(last) = out.last() {
+            if last.connections.nmap == connections.nmap {
+                continue;
+            }
+        }
+        out.push(TopologyEpoch {
+            offset_msec: offset,
+            connections,
+        });
+    }
+    out
+}
+
+// Exponentially distributed random value with the given mean
+fn exponential<R: Rng>(rng: &mut R, mean: f64) -> f64 {
+    -mean * (1.0 - rng.gen::<f64>()).ln()
+}
+
+// Alternating up and down times, each exponentially distributed with the given mean, starting
+// up. Returns the down windows, cut off at duration_msec.
+fn random_windows<R: Rng>(
+    rng: &mut R,
+    mean_up_msec: f64,
+    mean_down_msec: f64,
+    duration_msec: u64,
+) -> Vec<DowntimeWindow> {
+    let mut windows = Vec::new();
+    let mut t = exponential(rng, mean_up_msec) as u64;
+    while t < duration_msec {
+        let up = (t + exponential(rng, mean_down_msec).max(1.0) as u64).min(duration_msec);
+        windows.push(DowntimeWindow {
+            down_offset_msec: t,
+            up_offset_msec: up,
+        });
+        t = up + exponential(rng, mean_up_msec).max(1.0) as u64;
+    }
+    windows
+}
+
+// Random churn: while online, each peer fails at rate_per_min times per minute on average
+// (a Poisson process), then stays offline for an exponentially distributed time with mean
+// mean_downtime_msec. Windows are cut off at duration_msec. Peers that never fail are left out.
+pub fn random_churn<R: Rng>(
+    rng: &mut R,
+    peers: &[PeerId],
+    rate_per_min: f64,
+    mean_downtime_msec: f64,
+    duration_msec: u64,
+) -> HashMap<PeerId, Vec<DowntimeWindow>> {
+    assert!(rate_per_min > 0.0 && mean_downtime_msec > 0.0);
+    let mean_uptime_msec = 60_000.0 / rate_per_min;
+    let mut churn = HashMap::new();
+    for p in peers {
+        let windows = random_windows(rng, mean_uptime_msec, mean_downtime_msec, duration_msec);
+        if !windows.is_empty() {
+            churn.insert(p.clone(), windows);
+        }
+    }
+    churn
+}
+
+// Pick a fraction of the graph's links to flap: go down and back up during the test. Periodic
+// links are up for up_msec then down for down_msec, over and over, each from a random point in
+// the cycle; random links are up and down for exponentially distributed times with those means.
+pub fn link_flaps<R: Rng>(
+    rng: &mut R,
+    graph: &PeerGraph,
+    fraction: f64,
+    mode: FlapMode,
+    up_msec: u64,
+    down_msec: u64,
+    duration_msec: u64,
+) -> Vec<LinkFlap> {
+    assert!((0.0..=1.0).contains(&fraction) && up_msec > 0 && down_msec > 0);
+    let mut links: Vec<(&PeerId, &PeerId)> = graph
+        .nmap
+        .iter()
+        .flat_map(|(u, neighbors)| neighbors.iter().map(move |v| (u, v)))
+        .collect();
+    links.sort();
+    let amount = (fraction * links.len() as f64).round() as usize;
+    let mut chosen: Vec<(&PeerId, &PeerId)> = links.choose_multiple(rng, amount).cloned().collect();
+    chosen.sort();
+
+    let period = up_msec + down_msec;
+    let mut flaps = Vec::new();
+    for (dialer, listener) in chosen {
+        let down = match mode {
+            FlapMode::Periodic => {
+                // Start somewhere in the cycle, so links don't all flap together
+                let mut t = up_msec as i64 - rng.gen_range(0..period) as i64;
+                let mut windows = Vec::new();
+                while t < duration_msec as i64 {
+                    let up = (t + down_msec as i64).min(duration_msec as i64);
+                    if up > 0 {
+                        windows.push(DowntimeWindow {
+                            down_offset_msec: t.max(0) as u64,
+                            up_offset_msec: up as u64,
+                        });
+                    }
+                    t += period as i64;
+                }
+                windows
+            }
+            FlapMode::Random => {
+                random_windows(rng, up_msec as f64, down_msec as f64, duration_msec)
+            }
+        };
+        flaps.push(LinkFlap {
+            dialer: dialer.clone(),
+            listener: listener.clone(),
+            down,
+        });
+    }
+    flaps
+}
+
+// Visits of each mule to k clusters in turn, from the start of the test: dwell_msec at a cluster,
+// then transit_msec linked to none. Mule j starts at cluster j % k, so mules are spread out.
+pub fn mule_visits(
+    mules: &[PeerId],
+    k: usize,
+    dwell_msec: u64,
+    transit_msec: u64,
+    duration_msec: u64,
+) -> Vec<MuleVisit> {
+    assert!(k > 0 && dwell_msec > 0);
+    let mut visits = Vec::new();
+    for (j, mule) in mules.iter().enumerate() {
+        let mut t = 0;
+        let mut cluster = j % k;
+        while t < duration_msec {
+            visits.push(MuleVisit {
+                mule: mule.clone(),
+                cluster,
+                arrive_offset_msec: t,
+                leave_offset_msec: (t + dwell_msec).min(duration_msec),
+            });
+            t += dwell_msec + transit_msec;
+            cluster = (cluster + 1) % k;
+        }
+    }
+    visits
+}
+
+// Epochs of the base graph plus, during each visit, links from the mule to every peer in the
+// cluster it visits. An epoch starts wherever a mule arrives or leaves.
+pub fn mule_epochs(base: &PeerGraph, schedule: &MuleSchedule) -> Vec<TopologyEpoch> {
+    let mut offsets: Vec<u64> = std::iter::once(0)
+        .chain(
+            schedule
+                .visits
+                .iter()
+                .flat_map(|v| [v.arrive_offset_msec, v.leave_offset_msec]),
+        )
+        .collect();
+    offsets.sort_unstable();
+    offsets.dedup();
+
+    let mut epochs: Vec<TopologyEpoch> = Vec::new();
+    for offset in offsets {
+        let mut connections = base.clone();
+        for v in &schedule.visits {
+            if v.arrive_offset_msec <= offset && offset < v.leave_offset_msec {
+                for p in &schedule.clusters[v.cluster] {
+                    connections.add_edge(&v.mule, p);
+                }
+            }
+        }
+        if let Some(last) = epochs.last() {
+            if last.connections.nmap == connections.nmap {
+                continue;
+            }
+        }
+        epochs.push(TopologyEpoch {
+            offset_msec: offset,
+            connections,
+        });
+    }
+    epochs
+}
+
+// Explicit churn from a JSON object mapping short peer IDs to lists of [down, up] times in
+// seconds after the start, e.g. {"3": [[10, 20], [40, 45.5]]}. Keyed by short ID, since peer IDs
+// aren't known until peers join; see map_churn().
+pub fn load_churn_file(
+    path: &Path,
+) -> Result<HashMap<String, Vec<DowntimeWindow>>, Box<dyn Error>> {
+    let by_id: HashMap<String, Vec<(f64, f64)>> = serde_json::from_str(&fs::read_to_string(path)?)
+        .map_err(|e| format!("Bad churn file {}: {}", path.display(), e))?;
+    let mut churn = HashMap::new();
+    for (id, times) in by_id {
+        let mut windows = Vec::new();
+        for (down, up) in times {
+            if !(0.0 <= down && down < up) {
+                return Err(format!("Bad downtime [{}, {}] for peer {}", down, up, id).into());
+            }
+            windows.push(DowntimeWindow {
+                down_offset_msec: (down * 1000.0) as u64,
+                up_offset_msec: (up * 1000.0) as u64,
+            });
+        }
+        windows.sort_by_key(|w| w.down_offset_msec);
+        churn.insert(id, windows);
+    }
+    Ok(churn)
+}
+
+// Key churn loaded with load_churn_file() by the peers' full IDs
+pub fn map_churn(
+    by_short_id: &HashMap<String, Vec<DowntimeWindow>>,
+    peers: &[PeerId],
+) -> Result<HashMap<PeerId, Vec<DowntimeWindow>>, Box<dyn Error>> {
+    let ids: HashMap<String, &PeerId> = peers.iter().map(|p| (short_peer_id(p), p)).collect();
+    let mut churn = HashMap::new();
+    for (short_id, windows) in by_short_id {
+        let p = ids
+            .get(short_id)
+            .ok_or_else(|| format!("Churn for peer {}, which is not a peer", short_id))?;
+        churn.insert((*p).clone(), windows.clone());
+    }
+    Ok(churn)
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    use super::*;
+    use crate::graph::analytics::connected_components;
+    use crate::graph::test_util::to_peer_ids_vec;
+    use crate::graph::{complete_graph, orient_edges, orient_epochs, ring_graph, split_clusters};
+
+    #[test]
+    fn test_partition_epochs() {
+        let peers = to_peer_ids_vec(0..12);
+        let ring = ring_graph(&peers);
+        let schedule = PartitionSchedule {
+            split_offset_msec: 20_000,
+            heal_offset_msec: 40_000,
+            partitions: split_clusters(&peers, 3),
+        };
+        let epochs = partition_epochs(&ring, &[], &schedule);
+        let offsets: Vec<u64> = epochs.iter().map(|e| e.offset_msec).collect();
+        assert_eq!(offsets, vec![0, 20_000, 40_000]);
+        assert_eq!(epochs[0].connections.nmap, ring.nmap);
+        assert_eq!(epochs[2].connections.nmap, ring.nmap);
+        let mut comps = connected_components(&epochs[1].connections);
+        for c in comps.iter_mut() {
+            c.sort();
+        }
+        comps.sort();
+        let mut expected = schedule.partitions.clone();
+        for p in expected.iter_mut() {
+            p.sort();
+        }
+        expected.sort();
+        assert_eq!(comps, expected);
+
+        // Existing epochs keep their own graphs, minus cross-partition links during the split
+        let complete = complete_graph(&peers);
+        let moving = vec![
+            TopologyEpoch {
+                offset_msec: 0,
+                connections: ring.clone(),
+            },
+            TopologyEpoch {
+                offset_msec: 30_000,
+                connections: complete.clone(),
+            },
+        ];
+        let epochs = partition_epochs(&ring, &moving, &schedule);
+        let offsets: Vec<u64> = epochs.iter().map(|e| e.offset_msec).collect();
+        assert_eq!(offsets, vec![0, 20_000, 30_000, 40_000]);
+        assert_eq!(
+            epochs[2].connections.nmap,
+            split_graph(&complete, &schedule.partitions).nmap
+        );
+        assert_eq!(epochs[3].connections.nmap, complete.nmap);
+        assert_eq!(connected_components(&epochs[2].connections).len(), 3);
+    }
+
+    #[test]
+    fn test_random_churn() {
+        let peers = to_peer_ids_vec(0..50);
+        let churn = random_churn(&mut StdRng::seed_from_u64(9), &peers, 2.0, 5000.0, 120_000);
+        assert!(!churn.is_empty());
+        let mut down_msec = 0;
+        for windows in churn.values() {
+            for w in windows {
+                assert!(w.down_offset_msec < w.up_offset_msec && w.up_offset_msec <= 120_000);
+                down_msec += w.up_offset_msec - w.down_offset_msec;
+            }
+            for (a, b) in windows.iter().zip(&windows[1..]) {
+                assert!(a.up_offset_msec < b.down_offset_msec);
+            }
+        }
+        // About 2 failures per minute of uptime, 5 sec each: offline a sixth of the time or so
+        let fraction = down_msec as f64 / (50.0 * 120_000.0);
+        assert!(0.05 < fraction && fraction < 0.35, "{}", fraction);
+    }
+
+    #[test]
+    fn test_link_flaps() {
+        let peers = to_peer_ids_vec(0..20);
+        let ring = ring_graph(&peers);
+        let mut rng = StdRng::seed_from_u64(4);
+        let periodic = link_flaps(&mut rng, &ring, 0.5, FlapMode::Periodic, 8000, 2000, 60_000);
+        assert_eq!(periodic.len(), 10);
+        for f in &periodic {
+            assert!(ring.nmap[&f.dialer].contains(&f.listener));
+            assert!(f.down.len() >= 5 && f.down.len() <= 7);
+            for w in &f.down {
+                assert!(w.up_offset_msec <= 60_000);
+                assert!(w.up_offset_msec - w.down_offset_msec <= 2000);
+            }
+            // Only the first and last windows can be cut short by the test bounds
+            for (a, b) in f.down[1..].iter().zip(&f.down[2..]) {
+                assert_eq!(b.down_offset_msec - a.down_offset_msec, 10_000);
+            }
+        }
+
+        let random = link_flaps(&mut rng, &ring, 1.0, FlapMode::Random, 8000, 2000, 600_000);
+        assert_eq!(random.len(), 20);
+        let down_msec: u64 = random
+            .iter()
+            .flat_map(|f| &f.down)
+            .map(|w| w.up_offset_msec - w.down_offset_msec)
+            .sum();
+        // Down about a fifth of the time
+        let fraction = down_msec as f64 / (20.0 * 600_000.0);
+        assert!(0.12 < fraction && fraction < 0.28, "{}", fraction);
+        assert!(link_flaps(&mut rng, &ring, 0.0, FlapMode::Random, 1, 1, 1).is_empty());
+    }
+
+    #[test]
+    fn test_link_flaps_across_epochs() {
+        let peers = to_peer_ids_vec(0..12);
+        let mut rng = StdRng::seed_from_u64(6);
+        // Overlapping graphs, each oriented on its own
+        let mut epochs: Vec<TopologyEpoch> = [ring_graph(&peers), complete_graph(&peers[..6])]
+            .into_iter()
+            .enumerate()
+            .map(|(i, mut connections)| {
+                for p in &peers {
+                    connections.add_vertex(p);
+                }
+                orient_edges(&mut rng, &mut connections, EdgeOrientation::Random);
+                TopologyEpoch {
+                    offset_msec: i as u64 * 10_000,
+                    connections,
+                }
+            })
+            .collect();
+        orient_epochs(&mut rng, &mut epochs, EdgeOrientation::Random);
+        let mut links = PeerGraph::new();
+        for e in &epochs {
+            links.merge(e.connections.clone());
+        }
+        let flaps = link_flaps(
+            &mut rng,
+            &links,
+            1.0,
+            FlapMode::Periodic,
+            3000,
+            1000,
+            20_000,
+        );
+        assert_eq!(flaps.len(), 12 + 15 - 5);
+        for f in &flaps {
+            for e in &epochs {
+                if e.connections.has_edge(&f.dialer, &f.listener) {
+                    assert!(e.connections.nmap[&f.dialer].contains(&f.listener));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_mule_epochs() {
+        let peers = to_peer_ids_vec(0..8);
+        let (mules, rest) = peers.split_at(2);
+        let clusters = split_clusters(rest, 3);
+        let mut base = PeerGraph::new();
+        for c in &clusters {
+            base.merge(complete_graph(c));
+        }
+        for m in mules {
+            base.add_vertex(m);
+        }
+        let visits = mule_visits(mules, 3, 20_000, 10_000, 60_000);
+        let arrivals: Vec<(usize, u64)> = visits
+            .iter()
+            .filter(|v| v.mule == mules[1])
+            .map(|v| (v.cluster, v.arrive_offset_msec))
+            .collect();
+        assert_eq!(arrivals, vec![(1, 0), (2, 30_000)]);
+        let schedule = MuleSchedule { clusters, visits };
+        let epochs = mule_epochs(&base, &schedule);
+        let offsets: Vec<u64> = epochs.iter().map(|e| e.offset_msec).collect();
+        assert_eq!(offsets, vec![0, 20_000, 30_000, 50_000]);
+
+        // Each mule links to exactly one cluster while visiting, and the clusters only ever
+        // connect through a mule
+        assert_eq!(epochs[0].connections.degree(&mules[0]), 2);
+        assert_eq!(epochs[1].connections.degree(&mules[0]), 0);
+        assert!(epochs[2]
+            .connections
+            .has_edge(&mules[0], &schedule.clusters[1][0]));
+        for e in &epochs {
+            let mut without_mules = e.connections.clone();
+            for m in mules {
+                for p in rest {
+                    without_mules.remove_edge(m, p);
+                }
+            }
+            assert_eq!(connected_components(&without_mules).len(), 5);
+        }
+    }
+
+    #[test]
+    fn test_load_churn_file() {
+        let path = std::env::temp_dir().join("cmesh-test-churn.json");
+        fs::write(&path, r#"{"1": [[40, 45.5], [10, 20]], "12": [[0, 1]]}"#).unwrap();
+        let churn = load_churn_file(&path).unwrap();
+        assert_eq!(
+            churn["1"],
+            vec![
+                DowntimeWindow {
+                    down_offset_msec: 10_000,
+                    up_offset_msec: 20_000
+                },
+                DowntimeWindow {
+                    down_offset_msec: 40_000,
+                    up_offset_msec: 45_500
+                },
+            ]
+        );
+        let peers = vec!["peer1_a1b2".to_string(), "peer12_c3d4".to_string()];
+        let mapped = map_churn(&churn, &peers).unwrap();
+        assert_eq!(mapped["peer12_c3d4"].len(), 1);
+        assert!(map_churn(&churn, &peers[..1]).is_err());
+
+        fs::write(&path, r#"{"1": [[20, 10]]}"#).unwrap();
+        assert!(load_churn_file(&path).is_err());
+        fs::remove_file(&path).unwrap();
+    }
+}
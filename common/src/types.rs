@@ -13,6 +13,9 @@ pub type PeerId = String;
 // Keep serialized types here up to date with ts/src/types.ts
 // TODO codegen
 
+// The random suffix keeps IDs unique across restarts. It isn't derived from the plan seed,
+// since peers pick their IDs before there is a plan; anything that must be reproducible
+// should key on the prefix (device name) instead.
 pub fn random_peer_id(prefix: Option<&str>) -> PeerId {
     let pre;
     if prefix.is_none() {
@@ -44,49 +47,212 @@ fn short_peer_str(peer_name: &str) -> String {
     }
 }
 
+// Serialized as just nmap, so plans keep the same shape; links is rebuilt on deserialization.
 #[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(
+    from = "HashMap<PeerId, HashSet<PeerId>>",
+    into = "HashMap<PeerId, HashSet<PeerId>>"
+)]
 pub struct PeerGraph {
-    #[serde(flatten)]
+    // Directed edges. Read freely, but change the graph through add_edge() and friends so that
+    // links stays in sync.
     pub nmap: HashMap<PeerId, HashSet<PeerId>>,
+    // Undirected neighbors of each vertex
+    links: HashMap<PeerId, HashSet<PeerId>>,
+}
+
+impl From<HashMap<PeerId, HashSet<PeerId>>> for PeerGraph {
+    fn from(nmap: HashMap<PeerId, HashSet<PeerId>>) -> Self {
+        let mut graph = PeerGraph::new();
+        for (u, neighbors) in nmap {
+            graph.add_vertex(&u);
+            for v in neighbors {
+                graph.add_edge(&u, &v);
+            }
+        }
+        graph
+    }
+}
+
+impl From<PeerGraph> for HashMap<PeerId, HashSet<PeerId>> {
+    fn from(graph: PeerGraph) -> Self {
+        graph.nmap
+    }
 }
 
 impl PeerGraph {
     pub fn new() -> Self {
         Self {
             nmap: HashMap::new(),
+            links: HashMap::new(),
         }
     }
 
     // Treat each directed edge as an undirected edge, and return the set of neighbors for vertex
     pub fn undirected_links(&self, vertex: &PeerId) -> Option<HashSet<PeerId>> {
-        let v = self.nmap.get(vertex)?;
-        let mut peers = v.clone();
-        for (u, neighbors) in &self.nmap {
-            if neighbors.contains(vertex) {
-                peers.insert(u.clone());
+        self.links.get(vertex).cloned()
+    }
+
+    // Like undirected_links(), without the copy
+    pub fn links(&self, vertex: &PeerId) -> Option<&HashSet<PeerId>> {
+        self.links.get(vertex)
+    }
+
+    // Number of undirected neighbors, 0 for unknown vertices
+    pub fn degree(&self, vertex: &PeerId) -> usize {
+        self.links.get(vertex).map_or(0, |l| l.len())
+    }
+
+    // Add vertex with no outgoing edges, if not already present
+    pub fn add_vertex(&mut self, vertex: &PeerId) {
+        self.nmap.entry(vertex.clone()).or_insert_with(HashSet::new);
+        self.links.entry(vertex.clone()).or_default();
+    }
+
+    // Add directed edge u -> v, adding either vertex if needed
+    pub fn add_edge(&mut self, u: &PeerId, v: &PeerId) {
+        self.add_vertex(u);
+        self.add_vertex(v);
+        self.nmap.get_mut(u).unwrap().insert(v.clone());
+        self.links.get_mut(u).unwrap().insert(v.clone());
+        self.links.get_mut(v).unwrap().insert(u.clone());
+    }
+
+    // Remove the edge between u and v, in whichever direction it exists
+    pub fn remove_edge(&mut self, u: &PeerId, v: &PeerId) {
+        for (a, b) in [(u, v), (v, u)] {
+            if let Some(n) = self.nmap.get_mut(a) {
+                n.remove(b);
+            }
+            if let Some(n) = self.links.get_mut(a) {
+                n.remove(b);
             }
         }
-        Some(peers)
     }
 
-    pub fn to_dot(&self) -> String {
-        let mut dot = String::new();
-        dot.push_str("digraph G {\n");
-        for (u, v) in &self.nmap {
-            for v in v {
-                dot.push_str(&format!("  {} -> {};\n", short_peer_str(u), short_peer_str(v)));
+    // Add all of other's vertices and edges
+    pub fn merge(&mut self, other: PeerGraph) {
+        for (u, neighbors) in other.nmap {
+            self.add_vertex(&u);
+            for v in neighbors {
+                self.add_edge(&u, &v);
             }
         }
-        dot.push_str("}\n");
-        dot
+    }
+
+    // Remove every edge, keeping the vertices
+    pub fn clear_edges(&mut self) {
+        for n in self.nmap.values_mut().chain(self.links.values_mut()) {
+            n.clear();
+        }
+    }
+
+    // True if there is an edge between u and v in either direction
+    pub fn has_edge(&self, u: &PeerId, v: &PeerId) -> bool {
+        self.links.get(u).map_or(false, |n| n.contains(v))
     }
 }
 
-#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
 pub enum GraphType {
     Complete,
     SpanningTree,
     LAModel,
+    Random,
+    BarabasiAlbert,
+    SmallWorld,
+    Grid,
+    Torus,
+    Geometric,
+    Ring,
+    Line,
+    Star,
+    Islands,
+    KConnected,
+    Hierarchy,
+    Gateway,
+    RandomWaypoint,
+    Trace,
+    DataMule,
+    File,
+}
+
+// Which side of each link dials (u -> v: u calls connect(), v listens)
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+pub enum EdgeOrientation {
+    AsGenerated,  // Whatever the graph generator picked
+    Balanced,     // Spread dials as evenly as possible
+    Random,       // Either side, with equal probability
+    LowerIdDials, // The peer with the lower ID dials
+    HubListens,   // The peer with fewer links dials
+}
+
+// Parameters used to generate the connection graph, recorded in the plan for reference.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct GraphParams {
+    pub graph_type: GraphType,
+    // Edge probability p for GraphType::Random
+    pub edge_probability: Option<f64>,
+    // Edges added per new peer (m) for GraphType::BarabasiAlbert
+    pub attach_edges: Option<usize>,
+    // Ring lattice degree (k) and rewiring probability (beta) for GraphType::SmallWorld
+    pub lattice_degree: Option<usize>,
+    pub rewire_probability: Option<f64>,
+    // Lattice dimensions for GraphType::Grid and GraphType::Torus
+    pub grid_rows: Option<usize>,
+    pub grid_cols: Option<usize>,
+    // Side length of the square area peers are placed in, and the distance within which two
+    // peers connect, for GraphType::Geometric
+    pub area_size: Option<f64>,
+    pub radio_range: Option<f64>,
+    // Center peer for GraphType::Star
+    pub star_hub: Option<PeerId>,
+    // Number of clusters, bridge peers per cluster, and the graph type within each cluster,
+    // for GraphType::Islands. GraphType::DataMule uses clusters and cluster_graph too.
+    pub clusters: Option<usize>,
+    pub bridges_per_cluster: Option<usize>,
+    pub cluster_graph: Option<GraphType>,
+    // Path of the graph file the coordinator loaded, for GraphType::File, or of the contact
+    // trace, for GraphType::Trace
+    pub graph_file: Option<String>,
+    // Vertex connectivity (k) for GraphType::KConnected
+    pub connectivity: Option<usize>,
+    // Group size at each tier, from the bottom up, for GraphType::Hierarchy
+    pub tier_fanout: Option<Vec<usize>>,
+    // Number of gateway peers, and gateways each edge peer connects to, for GraphType::Gateway
+    pub gateways: Option<usize>,
+    pub gateway_uplinks: Option<usize>,
+    // Speed range (area units per second), pause at each waypoint, and how often positions are
+    // sampled into topology epochs, for GraphType::RandomWaypoint. Also uses area_size and
+    // radio_range.
+    pub min_speed: Option<f64>,
+    pub max_speed: Option<f64>,
+    pub pause_msec: Option<u64>,
+    pub mobility_tick_msec: Option<u64>,
+    // Trace time, in seconds, replayed from the start of the test, for GraphType::Trace
+    pub trace_start_sec: Option<f64>,
+    // Number of mules, and how long each stays at a cluster and travels between clusters, for
+    // GraphType::DataMule
+    pub mules: Option<usize>,
+    pub mule_dwell_sec: Option<f64>,
+    pub mule_transit_sec: Option<f64>,
+    // Most links any peer may have, applied after generating any graph type
+    pub max_degree: Option<usize>,
+    // Applied after generating any graph type
+    pub orientation: EdgeOrientation,
+}
+
+// Layout coordinates of a peer, used for drawing and for geometric graphs
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct Position {
+    pub x: f64,
+    pub y: f64,
+}
+
+impl Position {
+    pub fn distance(&self, other: &Position) -> f64 {
+        (self.x - other.x).hypot(self.y - other.y)
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, Hash, PartialEq, Eq)]
@@ -97,6 +263,7 @@ pub enum PeerState {
     Running,    // Executing
     Reporting,  // Finished test, outputting results
     Shutdown,   // Done, exiting
+    Offline,    // Executing, but stopped syncing as the plan's churn schedule says
 }
 
 impl Display for PeerState {
@@ -107,6 +274,7 @@ impl Display for PeerState {
             PeerState::Running => "Running",
             PeerState::Reporting => "Reporting",
             PeerState::Shutdown => "Shutdown",
+            PeerState::Offline => "Offline",
         };
         write!(f, "{}", s)
     }
@@ -118,6 +286,26 @@ pub struct Peer {
     pub peer_ip_addr: String,
     pub peer_port: u16,
     pub state: PeerState,
+    // Set by the coordinator in the execution plan for topologies with a spatial layout
+    pub position: Option<Position>,
+    // Set by the coordinator in the execution plan for topologies that group peers, e.g. the
+    // cluster a peer belongs to
+    pub group: Option<String>,
+    // Set by the coordinator in the execution plan for topologies where some peers play a
+    // special part
+    pub role: Option<PeerRole>,
+    // Set by the coordinator in the execution plan for hierarchical topologies, where 0 is the
+    // bottom tier
+    pub tier: Option<usize>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Hash, PartialEq, Eq)]
+#[serde(rename_all = "PascalCase")]
+pub enum PeerRole {
+    Bridge,  // Links its cluster to other clusters
+    Gateway, // Links edge peers to each other and to other gateways
+    Edge,    // Only connects to gateways
+    Mule,    // Carries records between clusters, visiting one at a time
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
@@ -178,12 +366,130 @@ pub struct ExecutionPlan {
     pub max_msg_delay_msec: u32,
     pub peers: Vec<Peer>,
     pub connections: PeerGraph,
+    pub graph_params: GraphParams,
+    // Seed for all randomness in the test: the coordinator's graph generation and peer doc ID,
+    // and each peer's message delays (see util::derive_seed)
+    pub seed: u64,
+    // Changes to the connection graph during the test, ordered by offset. Empty for a fixed
+    // mesh; otherwise the first epoch starts at offset 0 and `connections` is its graph.
+    pub epochs: Vec<TopologyEpoch>,
+    // When the mesh is split into partitions, if it is. The split itself is carried out by the
+    // epochs; this tells peers which records to wait for after the heal.
+    pub partition: Option<PartitionSchedule>,
+    // Times each peer stops syncing and later starts again, ordered and not overlapping. Peers
+    // without an entry stay online.
+    pub churn: HashMap<PeerId, Vec<DowntimeWindow>>,
+    // Links that go down and back up during the test
+    pub link_flaps: Vec<LinkFlap>,
+    // Clusters and mule visits for GraphType::DataMule. As with partitions, the links are
+    // carried out by the epochs; this tells peers when records from other clusters can arrive.
+    pub mules: Option<MuleSchedule>,
+}
+
+impl ExecutionPlan {
+    // True if the links in connections stay as they are for the whole test
+    pub fn is_static(&self) -> bool {
+        self.epochs.is_empty()
+            && self.partition.is_none()
+            && self.link_flaps.is_empty()
+            && self.mules.is_none()
+    }
+
+    // Index of the epoch in effect offset_msec after start_time, 0 for a fixed mesh
+    pub fn epoch_at(&self, offset_msec: u64) -> usize {
+        self.epochs
+            .iter()
+            .rposition(|e| e.offset_msec <= offset_msec)
+            .unwrap_or(0)
+    }
+
+    // Connection graph in effect during the given epoch
+    pub fn epoch_connections(&self, epoch: usize) -> &PeerGraph {
+        self.epochs
+            .get(epoch)
+            .map_or(&self.connections, |e| &e.connections)
+    }
+}
+
+// Links between different partitions are down from split_offset_msec until heal_offset_msec
+// after the plan's start_time
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct PartitionSchedule {
+    pub split_offset_msec: u64,
+    pub heal_offset_msec: u64,
+    pub partitions: Vec<Vec<PeerId>>,
+}
+
+impl PartitionSchedule {
+    // Index of the partition the peer is in, if any
+    pub fn partition_of(&self, peer: &PeerId) -> Option<usize> {
+        self.partitions.iter().position(|p| p.contains(peer))
+    }
+}
+
+// A peer is offline (not syncing) from down_offset_msec until up_offset_msec after the plan's
+// start_time
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DowntimeWindow {
+    pub down_offset_msec: u64,
+    pub up_offset_msec: u64,
+}
+
+// How flapping links alternate between up and down
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+pub enum FlapMode {
+    Periodic, // Fixed up and down times
+    Random,   // Exponentially distributed up and down times
+}
+
+// The link from dialer to listener is down during each window. The dialer carries this out by
+// leaving the listener out of the addresses it connects to.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct LinkFlap {
+    pub dialer: PeerId,
+    pub listener: PeerId,
+    pub down: Vec<DowntimeWindow>,
+}
+
+// Mule peers carry records between otherwise disconnected clusters, linking to one at a time
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct MuleSchedule {
+    pub clusters: Vec<Vec<PeerId>>,
+    pub visits: Vec<MuleVisit>,
+}
+
+impl MuleSchedule {
+    // Index of the cluster the peer is in, None for mules
+    pub fn cluster_of(&self, peer: &PeerId) -> Option<usize> {
+        self.clusters.iter().position(|c| c.contains(peer))
+    }
+}
+
+// The mule is linked to every peer in the cluster from arrive_offset_msec until
+// leave_offset_msec after the plan's start_time
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct MuleVisit {
+    pub mule: PeerId,
+    pub cluster: usize,
+    pub arrive_offset_msec: u64,
+    pub leave_offset_msec: u64,
+}
+
+// A connection graph in effect from offset_msec after the plan's start_time until the next epoch
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct TopologyEpoch {
+    pub offset_msec: u64,
+    pub connections: PeerGraph,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct PeerRecord {
     pub timestamp: u64,
     pub data: String,
+    // Position in the producer's sequence of records, from 1. 0 if the producer doesn't number
+    // its records.
+    #[serde(default)]
+    pub seq: u64,
 }
 
 // Bounded-size log of peer records
@@ -235,11 +541,54 @@ pub struct AvailabilityStats {
     pub down_time: Duration,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct PeerReport {
-    // After losing connection, how long until no events are older than max_msg_delay?
-    // TODO pub resync_latency: LatencyStats,
     pub message_latency: LatencyStats,
     // TODO pub db_availability: AvailabilityStats,
     pub records_produced: u64,
+    // Copied from our entry in the plan, so reports can be grouped
+    pub group: Option<String>,
+    pub role: Option<PeerRole>,
+    pub tier: Option<usize>,
+    // message_latency split by whether the sender is in our group, if we have one
+    pub group_latency: Option<LatencyStats>,
+    pub cross_group_latency: Option<LatencyStats>,
+    // message_latency split by the topology epoch each record was written in, if the plan has
+    // epochs
+    pub epoch_latency: Option<Vec<LatencyStats>>,
+    // After a partition heals, how long until we hold every record written in other partitions
+    // during the split. One event per peer we caught up with; distinct_peers counts all peers in
+    // other partitions. None if the plan has no partition.
+    pub resync_latency: Option<LatencyStats>,
+    // Records from peers in other partitions that never reached us, going by gaps in their
+    // sequence numbers: they were overwritten in the sender's log first. These can't count
+    // towards resync_latency, so a high number means it is an underestimate.
+    pub resync_missed_records: Option<u64>,
+    // Windows the plan took us offline for. None if it never did.
+    pub downtime: Option<Vec<DowntimeReport>>,
+    // How long after a mule arrived at our cluster each record from another cluster became
+    // visible to us. distinct_peers counts peers in other clusters. None unless the plan has
+    // mules and we are in a cluster.
+    // This is an approximation: each record is timed from the latest mule arrival before it
+    // reached us, which need not be the mule that carried it, and records overwritten in the
+    // sender's log before a mule picked them up are not timed at all.
+    pub mule_delivery: Option<LatencyStats>,
+    // Records from peers in other clusters that never reached us, going by gaps in their
+    // sequence numbers, as with resync_missed_records
+    pub mule_missed_records: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct DowntimeReport {
+    pub window: DowntimeWindow,
+    // How long after coming back until we hold every record other peers wrote while we were
+    // away, per peer: max_msec is the time to catch up with all of them
+    pub catch_up: LatencyStats,
+}
+
+// What each peer writes to the report collection when its test is done
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ReportDoc {
+    pub peer_id: PeerId,
+    pub report: PeerReport,
 }
@@ -45,6 +45,18 @@ pub fn concise_presence(pg: &PresenceGraph) -> String {
     out
 }
 
+// Derive a seed for one use of randomness (e.g. one peer's message delays) from the plan seed,
+// so each gets its own stream. FNV-1a, since std's hashers aren't guaranteed stable across
+// releases.
+pub fn derive_seed(seed: u64, label: &str) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for b in seed.to_le_bytes().iter().chain(label.as_bytes()) {
+        hash ^= *b as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
+
 pub fn print_cdoc(cbor: &serde_cbor::Value) -> Result<(), io::Error> {
     serde_json::to_writer_pretty(std::io::stdout(), cbor)?;
     Ok(())
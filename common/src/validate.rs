@@ -0,0 +1,490 @@
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::error::Error;
+
+use crate::graph::analytics::{connected_components, is_k_connected};
+use crate::types::{
+    DowntimeWindow, ExecutionPlan, GraphType, MuleSchedule, PartitionSchedule, PeerGraph, PeerId,
+};
+
+// Checks the coordinator runs before publishing a plan, so that a bad plan is refused up front
+// instead of failing on the peers mid-run.
+pub fn validate_plan(plan: &ExecutionPlan) -> Result<(), Box<dyn Error>> {
+    // Every peer has its own ID and its own address to dial
+    let mut ids: HashSet<&PeerId> = HashSet::new();
+    let mut addrs: HashMap<(&str, u16), &PeerId> = HashMap::new();
+    for p in &plan.peers {
+        if !ids.insert(&p.peer_id) {
+            return Err(format!("Peer {} appears more than once in the plan", p.peer_id).into());
+        }
+        if let Some(other) = addrs.insert((p.peer_ip_addr.as_str(), p.peer_port), &p.peer_id) {
+            return Err(format!(
+                "Peers {} and {} both use address {}:{}",
+                other, p.peer_id, p.peer_ip_addr, p.peer_port
+            )
+            .into());
+        }
+    }
+
+    if let Some(partition) = &plan.partition {
+        check_partition(plan, &ids, partition)?;
+    }
+
+    if let Some(mules) = &plan.mules {
+        check_mules(plan, &ids, mules)?;
+    }
+
+    let mut churned: Vec<&PeerId> = plan.churn.keys().collect();
+    churned.sort();
+    for p in churned {
+        if !ids.contains(p) {
+            return Err(format!("Peer {} in the churn schedule is not in the plan", p).into());
+        }
+        check_windows(&plan.churn[p], &format!("peer {}", p))?;
+    }
+
+    for flap in &plan.link_flaps {
+        let has_link = |g: &PeerGraph| {
+            g.nmap
+                .get(&flap.dialer)
+                .map_or(false, |n| n.contains(&flap.listener))
+        };
+        if !has_link(&plan.connections) && !plan.epochs.iter().any(|e| has_link(&e.connections)) {
+            return Err(format!(
+                "Flapping link {} -> {} is not in the connection graph",
+                flap.dialer, flap.listener
+            )
+            .into());
+        }
+        // The dialer carries out the flap, so it must dial the link wherever it exists
+        let epochs = plan.epochs.iter().map(|e| &e.connections);
+        if std::iter::once(&plan.connections)
+            .chain(epochs)
+            .any(|g| g.has_edge(&flap.dialer, &flap.listener) && !has_link(g))
+        {
+            return Err(format!(
+                "Flapping link {} -> {} is dialed the other way in some epoch",
+                flap.dialer, flap.listener
+            )
+            .into());
+        }
+        check_windows(
+            &flap.down,
+            &format!("link {} -> {}", flap.dialer, flap.listener),
+        )?;
+    }
+
+    check_graph(plan, &ids, &plan.connections, "connections")?;
+    if plan.epochs.is_empty() {
+        let components = connected_components(&plan.connections);
+        if components.len() > 1 {
+            let mut sizes: Vec<usize> = components.iter().map(|c| c.len()).collect();
+            sizes.sort_by(|a, b| b.cmp(a));
+            return Err(format!(
+                "Connection graph is not connected: {} components of sizes {:?}",
+                sizes.len(),
+                sizes
+            )
+            .into());
+        }
+        // Capping degrees afterwards may have cut links the k-connected graph needed
+        if let (GraphType::KConnected, Some(k)) =
+            (plan.graph_params.graph_type, plan.graph_params.connectivity)
+        {
+            if !is_k_connected(&plan.connections, k) {
+                return Err(format!("Connection graph is not {}-connected", k).into());
+            }
+        }
+        return Ok(());
+    }
+
+    // Epochs may split the mesh on purpose, so only their schedule and peers are checked
+    if plan.epochs[0].offset_msec != 0 {
+        return Err("First topology epoch must start at offset 0".into());
+    }
+    if plan.epochs[0].connections.nmap != plan.connections.nmap {
+        return Err("Plan connections differ from the first topology epoch".into());
+    }
+    for (i, w) in plan.epochs.windows(2).enumerate() {
+        if w[1].offset_msec <= w[0].offset_msec {
+            return Err(format!(
+                "Topology epoch {} starts at {} msec, not after epoch {} at {} msec",
+                i + 1,
+                w[1].offset_msec,
+                i,
+                w[0].offset_msec
+            )
+            .into());
+        }
+    }
+    for (i, epoch) in plan.epochs.iter().enumerate() {
+        check_graph(
+            plan,
+            &ids,
+            &epoch.connections,
+            &format!("topology epoch {}", i),
+        )?;
+    }
+    Ok(())
+}
+
+fn check_windows(windows: &[DowntimeWindow], name: &str) -> Result<(), Box<dyn Error>> {
+    let mut up_offset_msec = 0;
+    for w in windows {
+        if w.down_offset_msec >= w.up_offset_msec || w.down_offset_msec < up_offset_msec {
+            return Err(format!(
+                "Downtime windows of {} must be ordered and not overlap",
+                name
+            )
+            .into());
+        }
+        up_offset_msec = w.up_offset_msec;
+    }
+    Ok(())
+}
+
+// Every peer is in exactly one of at least two partitions, and the mesh heals after it splits
+fn check_partition(
+    plan: &ExecutionPlan,
+    ids: &HashSet<&PeerId>,
+    partition: &PartitionSchedule,
+) -> Result<(), Box<dyn Error>> {
+    if partition.partitions.len() < 2 {
+        return Err("Need at least two partitions".into());
+    }
+    if partition.split_offset_msec >= partition.heal_offset_msec {
+        return Err(format!(
+            "Partition heals at {} msec, not after the split at {} msec",
+            partition.heal_offset_msec, partition.split_offset_msec
+        )
+        .into());
+    }
+    let mut seen: HashSet<&PeerId> = HashSet::new();
+    for p in partition.partitions.iter().flatten() {
+        if !ids.contains(p) {
+            return Err(format!("Peer {} in a partition is not in the plan", p).into());
+        }
+        if !seen.insert(p) {
+            return Err(format!("Peer {} is in more than one partition", p).into());
+        }
+    }
+    if let Some(p) = plan.peers.iter().find(|p| !seen.contains(&p.peer_id)) {
+        return Err(format!("Peer {} is not in any partition", p.peer_id).into());
+    }
+    Ok(())
+}
+
+// Clusters don't overlap and don't include mules, each mule visits one cluster at a time, and
+// the clusters only ever connect through a mule
+fn check_mules(
+    plan: &ExecutionPlan,
+    ids: &HashSet<&PeerId>,
+    mules: &MuleSchedule,
+) -> Result<(), Box<dyn Error>> {
+    let mut seen: HashSet<&PeerId> = HashSet::new();
+    for p in mules.clusters.iter().flatten() {
+        if !ids.contains(p) {
+            return Err(format!("Peer {} in a mule cluster is not in the plan", p).into());
+        }
+        if !seen.insert(p) {
+            return Err(format!("Peer {} is in more than one mule cluster", p).into());
+        }
+    }
+    let mut visits: HashMap<&PeerId, Vec<DowntimeWindow>> = HashMap::new();
+    for v in &mules.visits {
+        if !ids.contains(&v.mule) || seen.contains(&v.mule) {
+            return Err(format!("Mule {} is not a peer outside the clusters", v.mule).into());
+        }
+        if v.cluster >= mules.clusters.len() {
+            return Err(format!("Mule {} visits unknown cluster {}", v.mule, v.cluster).into());
+        }
+        let window = DowntimeWindow {
+            down_offset_msec: v.arrive_offset_msec,
+            up_offset_msec: v.leave_offset_msec,
+        };
+        visits.entry(&v.mule).or_default().push(window);
+    }
+    let mut mule_ids: Vec<&PeerId> = visits.keys().copied().collect();
+    mule_ids.sort();
+    for m in mule_ids {
+        check_windows(&visits[m], &format!("visits of mule {}", m))?;
+    }
+
+    let clusters: BTreeSet<BTreeSet<PeerId>> = mules
+        .clusters
+        .iter()
+        .map(|c| c.iter().cloned().collect())
+        .collect();
+    for (i, epoch) in plan.epochs.iter().enumerate() {
+        // Keep only the links within clusters
+        let mut without_mules = PeerGraph::new();
+        for p in &seen {
+            without_mules.add_vertex(p);
+        }
+        for (u, neighbors) in &epoch.connections.nmap {
+            if !seen.contains(u) {
+                continue;
+            }
+            for v in neighbors.iter().filter(|v| seen.contains(v)) {
+                without_mules.add_edge(u, v);
+            }
+        }
+        let components: BTreeSet<BTreeSet<PeerId>> = connected_components(&without_mules)
+            .into_iter()
+            .map(|c| c.into_iter().collect())
+            .collect();
+        if components != clusters {
+            return Err(format!(
+                "Without mules, topology epoch {} does not split into the mule clusters",
+                i
+            )
+            .into());
+        }
+    }
+    Ok(())
+}
+
+// The graph covers exactly the plan's peers, and respects the plan's max degree
+fn check_graph(
+    plan: &ExecutionPlan,
+    ids: &HashSet<&PeerId>,
+    graph: &PeerGraph,
+    name: &str,
+) -> Result<(), Box<dyn Error>> {
+    let mut vertices: Vec<&PeerId> = graph.nmap.keys().collect();
+    vertices.sort();
+    for u in vertices {
+        for v in std::iter::once(u).chain(&graph.nmap[u]) {
+            if !ids.contains(v) {
+                return Err(format!("Peer {} in {} is not in the plan", v, name).into());
+            }
+        }
+    }
+    for p in &plan.peers {
+        if !graph.nmap.contains_key(&p.peer_id) {
+            return Err(format!("Peer {} has no entry in {}", p.peer_id, name).into());
+        }
+    }
+
+    if let Some(max_degree) = plan.graph_params.max_degree {
+        let mut over: Vec<(&PeerId, usize)> = plan
+            .peers
+            .iter()
+            .map(|p| (&p.peer_id, graph.degree(&p.peer_id)))
+            .filter(|(_, d)| *d > max_degree)
+            .collect();
+        if !over.is_empty() {
+            over.sort();
+            return Err(format!(
+                "{} peer(s) in {} have more than max degree {} links: {:?}",
+                over.len(),
+                name,
+                max_degree,
+                over
+            )
+            .into());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::graph::{complete_graph, harary_graph, line_graph, star_graph};
+    use crate::types::{LinkFlap, MuleVisit, Peer, PeerState, TopologyEpoch};
+
+    use super::*;
+
+    fn plan_for(n: usize) -> ExecutionPlan {
+        let ids: Vec<PeerId> = (0..n).map(|i| format!("peer{}_a1b2", i)).collect();
+        let mut plan = ExecutionPlan {
+            connections: line_graph(&ids),
+            ..Default::default()
+        };
+        for (i, id) in ids.iter().enumerate() {
+            plan.peers.push(Peer {
+                peer_id: id.clone(),
+                peer_ip_addr: "10.0.0.1".to_string(),
+                peer_port: 4000 + i as u16,
+                state: PeerState::Init,
+                position: None,
+                group: None,
+                role: None,
+                tier: None,
+            });
+        }
+        plan
+    }
+
+    #[test]
+    fn test_validate_plan() {
+        let plan = plan_for(5);
+        assert!(validate_plan(&plan).is_ok());
+
+        let mut dup_addr = plan.clone();
+        dup_addr.peers[3].peer_port = dup_addr.peers[1].peer_port;
+        assert!(validate_plan(&dup_addr).is_err());
+
+        let mut dup_id = plan.clone();
+        dup_id.peers[3].peer_id = dup_id.peers[1].peer_id.clone();
+        assert!(validate_plan(&dup_id).is_err());
+
+        let mut missing = plan.clone();
+        missing.peers.pop();
+        assert!(validate_plan(&missing).is_err());
+
+        let mut unlinked = plan.clone();
+        unlinked.connections.add_vertex(&"peer9_a1b2".to_string());
+        assert!(validate_plan(&unlinked).is_err());
+
+        let mut churned = plan.clone();
+        let window = |down_offset_msec, up_offset_msec| DowntimeWindow {
+            down_offset_msec,
+            up_offset_msec,
+        };
+        let p = churned.peers[2].peer_id.clone();
+        churned
+            .churn
+            .insert(p.clone(), vec![window(0, 100), window(100, 200)]);
+        assert!(validate_plan(&churned).is_ok());
+        churned
+            .churn
+            .insert(p, vec![window(0, 100), window(50, 200)]);
+        assert!(validate_plan(&churned).is_err());
+
+        let mut flapping = plan.clone();
+        let (a, b) = (
+            flapping.peers[0].peer_id.clone(),
+            flapping.peers[1].peer_id.clone(),
+        );
+        let flap = |dialer: &PeerId, listener: &PeerId| LinkFlap {
+            dialer: dialer.clone(),
+            listener: listener.clone(),
+            down: vec![window(10, 20)],
+        };
+        let forward = flapping.connections.nmap[&a].contains(&b);
+        let (dialer, listener) = if forward { (&a, &b) } else { (&b, &a) };
+        flapping.link_flaps = vec![flap(dialer, listener)];
+        assert!(validate_plan(&flapping).is_ok());
+        flapping.link_flaps = vec![flap(listener, dialer)];
+        assert!(validate_plan(&flapping).is_err());
+        flapping.link_flaps = vec![flap(dialer, listener)];
+        flapping.epochs = vec![
+            TopologyEpoch {
+                offset_msec: 0,
+                connections: flapping.connections.clone(),
+            },
+            TopologyEpoch {
+                offset_msec: 5000,
+                connections: flapping.connections.clone(),
+            },
+        ];
+        assert!(validate_plan(&flapping).is_ok());
+        let reversed = &mut flapping.epochs[1].connections;
+        reversed.remove_edge(dialer, listener);
+        reversed.add_edge(listener, dialer);
+        assert!(validate_plan(&flapping).is_err());
+
+        let mut split = plan.clone();
+        let (a, b) = (
+            split.peers[1].peer_id.clone(),
+            split.peers[2].peer_id.clone(),
+        );
+        split.connections.remove_edge(&a, &b);
+        assert!(validate_plan(&split).is_err());
+
+        let ids: Vec<PeerId> = plan.peers.iter().map(|p| p.peer_id.clone()).collect();
+        let mut limited = plan.clone();
+        limited.graph_params.max_degree = Some(2);
+        assert!(validate_plan(&limited).is_ok());
+        limited.connections = star_graph(&ids, &ids[0]);
+        assert!(validate_plan(&limited).is_err());
+        limited.connections = complete_graph(&ids);
+        limited.graph_params.max_degree = Some(4);
+        assert!(validate_plan(&limited).is_ok());
+
+        let mut k_connected = plan.clone();
+        k_connected.connections = harary_graph(&ids, 3);
+        k_connected.graph_params.graph_type = GraphType::KConnected;
+        k_connected.graph_params.connectivity = Some(3);
+        assert!(validate_plan(&k_connected).is_ok());
+        k_connected.connections.remove_edge(&ids[0], &ids[1]);
+        assert!(validate_plan(&k_connected).is_err());
+    }
+
+    #[test]
+    fn test_validate_epochs() {
+        let mut plan = plan_for(4);
+        let ids: Vec<PeerId> = plan.peers.iter().map(|p| p.peer_id.clone()).collect();
+        let mut split = line_graph(&ids[..2]);
+        split.merge(line_graph(&ids[2..]));
+        plan.epochs = vec![
+            TopologyEpoch {
+                offset_msec: 0,
+                connections: plan.connections.clone(),
+            },
+            TopologyEpoch {
+                offset_msec: 5000,
+                connections: split,
+            },
+        ];
+        assert!(validate_plan(&plan).is_ok());
+        assert_eq!(plan.epoch_at(0), 0);
+        assert_eq!(plan.epoch_at(4999), 0);
+        assert_eq!(plan.epoch_at(5000), 1);
+        assert_eq!(plan.epoch_at(60000), 1);
+        assert!(!plan.epoch_connections(1).has_edge(&ids[1], &ids[2]));
+
+        let mut unordered = plan.clone();
+        unordered.epochs[1].offset_msec = 0;
+        assert!(validate_plan(&unordered).is_err());
+
+        let mut stale = plan.clone();
+        stale.connections = complete_graph(&ids);
+        assert!(validate_plan(&stale).is_err());
+
+        let mut partitioned = plan.clone();
+        partitioned.partition = Some(PartitionSchedule {
+            split_offset_msec: 5000,
+            heal_offset_msec: 10000,
+            partitions: vec![ids[..2].to_vec(), ids[2..].to_vec()],
+        });
+        assert!(validate_plan(&partitioned).is_ok());
+        partitioned.partition.as_mut().unwrap().partitions[1].pop();
+        assert!(validate_plan(&partitioned).is_err());
+
+        let mut muled = plan.clone();
+        let mut at_second = line_graph(&ids[2..]);
+        at_second.add_vertex(&ids[1]);
+        at_second.add_edge(&ids[0], &ids[2]);
+        muled.connections = plan.epochs[1].connections.clone();
+        muled.epochs[0].connections = plan.epochs[1].connections.clone();
+        muled.epochs[1].connections = at_second;
+        let visit = |cluster, arrive_offset_msec, leave_offset_msec| MuleVisit {
+            mule: ids[0].clone(),
+            cluster,
+            arrive_offset_msec,
+            leave_offset_msec,
+        };
+        muled.mules = Some(MuleSchedule {
+            clusters: vec![vec![ids[1].clone()], ids[2..].to_vec()],
+            visits: vec![visit(0, 0, 5000), visit(1, 5000, 8000)],
+        });
+        assert!(validate_plan(&muled).is_ok());
+        let mut bridged = muled.clone();
+        bridged.epochs[1].connections.add_edge(&ids[1], &ids[2]);
+        assert!(validate_plan(&bridged).is_err());
+        let mules = muled.mules.as_mut().unwrap();
+        mules.visits.push(visit(0, 7000, 9000));
+        assert!(validate_plan(&muled).is_err());
+        let mules = muled.mules.as_mut().unwrap();
+        mules.visits.pop();
+        mules.clusters[0].push(ids[0].clone());
+        assert!(validate_plan(&muled).is_err());
+
+        plan.epochs[1].connections.remove_edge(&ids[0], &ids[1]);
+        plan.epochs[1]
+            .connections
+            .add_edge(&ids[0], &"peer9_a1b2".to_string());
+        assert!(validate_plan(&plan).is_err());
+    }
+}
@@ -1,19 +1,28 @@
 use clap::Parser;
 use clap::ValueEnum;
 use common::default::*;
+use common::graph::analytics::*;
+use common::graph::export::{self, GraphAttributes};
+use common::graph::import::*;
+use common::graph::trace::*;
 use common::graph::*;
+use common::mobility::*;
+use common::scenario::*;
 use common::types::PeerState::*;
 use common::types::*;
 use common::util::*;
+use common::validate::validate_plan;
 use dittolive_ditto::error::DittoError;
 use dittolive_ditto::prelude::*;
 use env_logger::Env;
 use log::*;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
 use std::collections::{HashMap, HashSet};
 use std::error::Error;
 use std::fs::File;
 use std::io::Write;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::sync::{Arc, Condvar, Mutex};
 
 #[derive(Parser, Debug)]
@@ -46,6 +55,174 @@ struct Cli {
           default_missing_value="complete", value_enum)]
     connection_graph: GraphType,
 
+    /// Edge probability for random graphs
+    #[arg(long, conflicts_with = "avg_degree")]
+    edge_probability: Option<f64>,
+
+    /// Target average degree for random graphs, used to derive edge probability
+    #[arg(long)]
+    avg_degree: Option<f64>,
+
+    /// Edges each new peer attaches with (m) for Barabási–Albert graphs
+    #[arg(long, default_value_t = GRAPH_BA_ATTACH_EDGES)]
+    attach_edges: usize,
+
+    /// Ring lattice degree (k) for small-world graphs
+    #[arg(long, default_value_t = GRAPH_WS_LATTICE_DEGREE)]
+    lattice_degree: usize,
+
+    /// Probability of rewiring each lattice edge (beta) for small-world graphs
+    #[arg(long, default_value_t = GRAPH_WS_REWIRE_PROBABILITY)]
+    rewire_probability: f64,
+
+    /// Rows for grid and torus graphs (default: as square as possible)
+    #[arg(long)]
+    grid_rows: Option<usize>,
+
+    /// Columns for grid and torus graphs (default: as square as possible)
+    #[arg(long)]
+    grid_cols: Option<usize>,
+
+    /// Side length of the square area peers are placed in, for geometric and random waypoint
+    /// graphs
+    #[arg(long, default_value_t = GRAPH_GEO_AREA_SIZE)]
+    area_size: f64,
+
+    /// Distance within which two peers connect, for geometric and random waypoint graphs
+    #[arg(long, default_value_t = GRAPH_GEO_RADIO_RANGE)]
+    radio_range: f64,
+
+    /// Short ID (e.g. "3" for peer3) of the hub for star graphs. Default: lowest peer ID.
+    #[arg(long)]
+    star_hub: Option<String>,
+
+    /// Number of clusters for islands and data-mule graphs
+    #[arg(long, default_value_t = GRAPH_ISLANDS_CLUSTERS)]
+    clusters: usize,
+
+    /// Bridge peers per cluster, which link clusters to each other, for islands graphs
+    #[arg(long, default_value_t = GRAPH_ISLANDS_BRIDGES)]
+    bridges_per_cluster: usize,
+
+    /// Graph type within each cluster of an islands or data-mule graph: complete or la-model
+    #[arg(long, default_value_t = GraphType::Complete, value_enum)]
+    cluster_graph: GraphType,
+
+    /// Graph file (.dot, .json edge list or .graphml) to load for --connection-graph=file, or
+    /// contact trace (CSV of t_start,t_end,node_a,node_b, or the ONE simulator's connection
+    /// events) to replay for --connection-graph=trace. Its node labels are mapped to peers' short
+    /// IDs if they match, otherwise in sorted order.
+    #[arg(long)]
+    graph_file: Option<String>,
+
+    /// Number of mule peers that carry records between the clusters of a data-mule graph
+    #[arg(long, default_value_t = GRAPH_MULES)]
+    mules: usize,
+
+    /// Seconds a mule stays linked to each cluster it visits, for data-mule graphs
+    #[arg(long, default_value_t = MULE_DWELL_SEC)]
+    mule_dwell_sec: f64,
+
+    /// Seconds a mule spends between clusters, linked to none, for data-mule graphs
+    #[arg(long, default_value_t = MULE_TRANSIT_SEC)]
+    mule_transit_sec: f64,
+
+    /// Trace time, in seconds, to replay from the start of the test, for trace graphs
+    #[arg(long, default_value_t = 0.0)]
+    trace_start_sec: f64,
+
+    /// Number of peers (k) that must be removed to disconnect a k-connected graph
+    #[arg(long, default_value_t = GRAPH_K_CONNECTIVITY)]
+    connectivity: usize,
+
+    /// Comma-separated group sizes for each tier of a hierarchy graph, from the bottom (teams)
+    /// up. The last one is reused for any further tiers.
+    #[arg(long, value_delimiter = ',', default_values_t = GRAPH_TIER_FANOUT)]
+    tier_fanout: Vec<usize>,
+
+    /// Number of gateway peers for gateway graphs
+    #[arg(long, default_value_t = GRAPH_GATEWAYS)]
+    gateways: usize,
+
+    /// Number of gateways each edge peer connects to, for gateway graphs
+    #[arg(long, default_value_t = GRAPH_GATEWAY_UPLINKS)]
+    gateway_uplinks: usize,
+
+    /// Slowest and fastest speed, in area units per second, for random waypoint graphs
+    #[arg(long, default_value_t = MOBILITY_MIN_SPEED)]
+    min_speed: f64,
+
+    #[arg(long, default_value_t = MOBILITY_MAX_SPEED)]
+    max_speed: f64,
+
+    /// How long peers stay at each waypoint, for random waypoint graphs
+    #[arg(long, default_value_t = MOBILITY_PAUSE_MSEC)]
+    pause_msec: u64,
+
+    /// How often peer positions are sampled into a new topology epoch, for random waypoint
+    /// graphs
+    #[arg(long, default_value_t = MOBILITY_TICK_MSEC)]
+    mobility_tick_msec: u64,
+
+    /// Split the mesh into this many partitions during the test and heal it again, to measure
+    /// resync latency. Applies to every graph type.
+    #[arg(long)]
+    partitions: Option<usize>,
+
+    /// Seconds after the start that the mesh splits (default: a third of the test duration)
+    #[arg(long)]
+    split_at_sec: Option<u32>,
+
+    /// Seconds after the start that the mesh heals (default: two thirds of the test duration)
+    #[arg(long)]
+    heal_at_sec: Option<u32>,
+
+    /// Average number of times per minute each peer goes offline (stops syncing), for random
+    /// churn
+    #[arg(long, conflicts_with = "churn_file")]
+    churn_rate: Option<f64>,
+
+    /// Average seconds a peer stays offline, for random churn
+    #[arg(long, default_value_t = CHURN_MEAN_DOWNTIME_SEC)]
+    churn_downtime_sec: f64,
+
+    /// JSON file with the times peers go offline and come back, in seconds after the start,
+    /// keyed by short peer ID: {"3": [[10, 20], [40, 45]]}
+    #[arg(long)]
+    churn_file: Option<String>,
+
+    /// Fraction of links that go down and come back up during the test, to measure how sync
+    /// copes with flaky links. Only the chosen links flap; peers stay online.
+    #[arg(long)]
+    flap_fraction: Option<f64>,
+
+    /// periodic: each link is down for --flap-down-sec out of every --flap-up-sec +
+    /// --flap-down-sec. random: up and down times are exponential with those means.
+    #[arg(long, default_value_t = FlapMode::Periodic, value_enum)]
+    flap_mode: FlapMode,
+
+    /// Seconds a flapping link stays up (average for random flapping)
+    #[arg(long, default_value_t = FLAP_UP_SEC)]
+    flap_up_sec: f64,
+
+    /// Seconds a flapping link stays down (average for random flapping)
+    #[arg(long, default_value_t = FLAP_DOWN_SEC)]
+    flap_down_sec: f64,
+
+    /// Most links any peer may have. Applies to every graph type: links of peers over the limit
+    /// are moved to peers with spare capacity, or dropped, keeping the graph connected.
+    #[arg(long)]
+    max_degree: Option<usize>,
+
+    /// Which side of each link dials, applied after generating any graph type
+    #[arg(long, default_value_t = EdgeOrientation::AsGenerated, value_enum)]
+    orientation: EdgeOrientation,
+
+    /// Seed for graph generation, the peer document ID and peers' message delays. Picked at
+    /// random and logged if not given, so any run can be repeated.
+    #[arg(long)]
+    seed: Option<u64>,
+
     #[arg(short, long, default_value = "/output")]
     output_dir: String,
 }
@@ -58,6 +235,8 @@ struct CoordinatorContext {
     hb_doc_id: Option<DocumentId>,
     hb_processor: Option<Arc<HeartbeatProcessor>>,
     hb_observer: Option<LiveQuery>,
+    report_collection: Option<Collection>,
+    report_subscription: Option<Subscription>,
     peers: Arc<Mutex<HashSet<Peer>>>,
     presence: Option<PresenceObserver>,
 }
@@ -105,6 +284,9 @@ fn init_transport(ctx: &mut CoordinatorContext, cli: &Cli) -> Result<(), Box<dyn
 struct HeartbeatProcessor {
     peer_set: Arc<Mutex<HashSet<Peer>>>,
     added: Condvar,
+    // Send time and age on arrival of the latest heartbeat from each peer. The age includes any
+    // clock skew between the peer's host and ours.
+    beat_age: Mutex<HashMap<PeerId, (u64, u64)>>,
     // To keep subscription alive as needed
     #[allow(dead_code)]
     subscription: Subscription,
@@ -113,8 +295,19 @@ struct HeartbeatProcessor {
 impl HeartbeatProcessor {
     fn process_heartbeat(&self, hbd: HeartbeatsDoc) {
         debug!("--> process {} peer heartbeats", hbd.beats.len());
+        let now = system_time_msec();
         for (_peer_id, hb) in hbd.beats {
             debug!("--> got heartbeat {:?}", hb);
+            // The doc holds every peer's latest beat, so only count the ones that are new
+            let mut beat_age = self.beat_age.lock().unwrap();
+            let last = beat_age.get(&hb.sender.peer_id).map(|(sent, _)| *sent);
+            if last.map_or(true, |sent| hb.sent_at_msec > sent) {
+                let age = now.saturating_sub(hb.sent_at_msec);
+                beat_age.insert(hb.sender.peer_id.clone(), (hb.sent_at_msec, age));
+                if hb.sender.state == Offline {
+                    info!("--> peer {} went offline as planned", hb.sender.peer_id);
+                }
+            }
             let mut peer_set = self.peer_set.lock().unwrap();
             peer_set.replace(hb.sender);
             trace!("--> peer set: {:?}", peer_set);
@@ -202,6 +395,7 @@ fn init_heartbeat_processor(ctx: &mut CoordinatorContext) -> Result<(), Box<dyn
     let cb = Arc::new(HeartbeatProcessor {
         peer_set: Arc::clone(&ctx.peers),
         added: Condvar::new(),
+        beat_age: Mutex::new(HashMap::new()),
         subscription: _hb_sub,
     });
     ctx.hb_processor = Some(cb.clone());
@@ -233,6 +427,45 @@ fn init_heartbeat_processor(ctx: &mut CoordinatorContext) -> Result<(), Box<dyn
     Ok(())
 }
 
+fn init_report_collection(
+    ctx: &mut CoordinatorContext,
+    report_collection: &str,
+) -> Result<(), Box<dyn Error>> {
+    // Subscribe before the test starts, so peer reports sync to us as they are written
+    let rc = ctx.ditto.store().collection(report_collection)?;
+    ctx.report_subscription = Some(rc.find_all().subscribe());
+    ctx.report_collection = Some(rc);
+    Ok(())
+}
+
+// Reports written so far, waiting up to REPORT_WAIT_SEC for one from each of min_peers peers
+fn collect_reports(
+    ctx: &CoordinatorContext,
+    min_peers: usize,
+) -> Result<HashMap<PeerId, PeerReport>, Box<dyn Error>> {
+    let rc = ctx.report_collection.as_ref().unwrap();
+    let deadline = system_time_msec() + REPORT_WAIT_SEC * 1000;
+    loop {
+        let mut reports = HashMap::new();
+        for doc in rc.find_all().exec()? {
+            match doc.typed::<ReportDoc>() {
+                Ok(rd) => {
+                    reports.insert(rd.peer_id, rd.report);
+                }
+                Err(e) => debug!("-> skipping unreadable report: {:?}", e),
+            }
+        }
+        if reports.len() >= min_peers {
+            return Ok(reports);
+        }
+        if system_time_msec() >= deadline {
+            warn!("Only got {} of {} peer reports", reports.len(), min_peers);
+            return Ok(reports);
+        }
+        std::thread::sleep(std::time::Duration::from_secs(QUERY_POLL_SEC));
+    }
+}
+
 fn wait_for_quorum(
     ctx: &mut CoordinatorContext,
     coord_collection: &str,
@@ -291,20 +524,50 @@ fn start_delay_secs(hbp: &HeartbeatProcessor) -> u64 {
     seconds
 }
 
+// Edge probability for random graphs, either given directly or derived from the average degree
+// we want: E[degree] = p * (n - 1)
+fn random_edge_probability(cli: &Cli, n: usize) -> f64 {
+    if let Some(p) = cli.edge_probability {
+        return p;
+    }
+    if n < 2 {
+        return 0.0;
+    }
+    let avg_degree = cli.avg_degree.unwrap_or(GRAPH_RANDOM_AVG_DEGREE);
+    (avg_degree / (n - 1) as f64).min(1.0)
+}
+
 fn generate_plan(
     ctx: &CoordinatorContext,
-    duration_sec: u32,
-    conn_graph: GraphType,
-) -> ExecutionPlan {
-    let mut plan = ExecutionPlan::default();
-    let mut peer_ids = Vec::new();
+    cli: &Cli,
+    graph_file: Option<&LabeledGraph>,
+    trace: Option<&ContactTrace>,
+    churn_file: Option<&HashMap<String, Vec<DowntimeWindow>>>,
+) -> Result<ExecutionPlan, Box<dyn Error>> {
+    let seed = cli.seed.unwrap_or_else(rand::random);
+    info!("--> seed {}", seed);
+    let mut rng = StdRng::seed_from_u64(seed);
+    let some_id = format!("{:x}", rng.gen::<u64>());
+    let mut plan = ExecutionPlan {
+        seed,
+        peer_doc_id: DocumentId::from(some_id.as_bytes()),
+        ..Default::default()
+    };
+    // Peers arrive in any order, so sort them for the generators. Peer IDs start with the
+    // device name, which makes this order the same from run to run.
     for p in ctx.peers.lock().unwrap().iter() {
         plan.peers.push(p.clone());
-        peer_ids.push(p.peer_id.clone());
     }
+    plan.peers.sort_by(|a, b| a.peer_id.cmp(&b.peer_id));
+    let peer_ids: Vec<PeerId> = plan.peers.iter().map(|p| p.peer_id.clone()).collect();
     plan.start_time = 0; // Start time not scheduled yet
+    plan.graph_params.graph_type = cli.connection_graph;
 
-    match conn_graph {
+    let mut positions = None;
+    let mut groups: HashMap<PeerId, String> = HashMap::new();
+    let mut roles: HashMap<PeerId, PeerRole> = HashMap::new();
+    let mut tiers: HashMap<PeerId, usize> = HashMap::new();
+    match cli.connection_graph {
         GraphType::Complete => {
             plan.connections = complete_graph(&peer_ids);
         }
@@ -313,13 +576,515 @@ fn generate_plan(
         }
         GraphType::LAModel => {
             if peer_ids.len() < GRAPH_LA_CLIQUE_SIZE {
-                panic!("Need at least {} peers for LA model", GRAPH_LA_CLIQUE_SIZE);
+                return Err(format!(
+                    "Need at least {} peers for LA model, got {}",
+                    GRAPH_LA_CLIQUE_SIZE,
+                    peer_ids.len()
+                )
+                .into());
             }
-            plan.connections = local_attachment_model(&peer_ids, GRAPH_LA_CLIQUE_SIZE);
+            plan.connections = local_attachment_model(&mut rng, &peer_ids, GRAPH_LA_CLIQUE_SIZE);
         }
+        GraphType::Random => {
+            let p = random_edge_probability(cli, peer_ids.len());
+            if !(0.0..=1.0).contains(&p) {
+                return Err(format!("Edge probability must be in [0, 1], got {}", p).into());
+            }
+            info!("--> random graph with edge probability {}", p);
+            plan.connections = random_graph(&mut rng, &peer_ids, p);
+            plan.graph_params.edge_probability = Some(p);
+        }
+        GraphType::BarabasiAlbert => {
+            if cli.attach_edges == 0 {
+                return Err("Attach edges must be at least 1".into());
+            }
+            plan.connections = barabasi_albert(&mut rng, &peer_ids, cli.attach_edges);
+            plan.graph_params.attach_edges = Some(cli.attach_edges);
+        }
+        GraphType::SmallWorld => {
+            if !(0.0..=1.0).contains(&cli.rewire_probability) {
+                return Err(format!(
+                    "Rewire probability must be in [0, 1], got {}",
+                    cli.rewire_probability
+                )
+                .into());
+            }
+            plan.connections = small_world(
+                &mut rng,
+                &peer_ids,
+                cli.lattice_degree,
+                cli.rewire_probability,
+            );
+            plan.graph_params.lattice_degree = Some(cli.lattice_degree);
+            plan.graph_params.rewire_probability = Some(cli.rewire_probability);
+        }
+        GraphType::Grid | GraphType::Torus => {
+            let (rows, cols) = grid_dimensions(peer_ids.len(), cli.grid_rows, cli.grid_cols);
+            info!("--> {} x {} lattice", rows, cols);
+            let wrap = cli.connection_graph == GraphType::Torus;
+            plan.connections = lattice(&peer_ids, rows, cols, wrap);
+            positions = Some(lattice_positions(&peer_ids, cols));
+            plan.graph_params.grid_rows = Some(rows);
+            plan.graph_params.grid_cols = Some(cols);
+        }
+        GraphType::Geometric => {
+            if !(cli.area_size > 0.0 && cli.radio_range > 0.0) {
+                return Err("Area size and radio range must be positive".into());
+            }
+            let (graph, pos) = geometric_graph(&mut rng, &peer_ids, cli.area_size, cli.radio_range);
+            plan.connections = graph;
+            positions = Some(pos);
+            plan.graph_params.area_size = Some(cli.area_size);
+            plan.graph_params.radio_range = Some(cli.radio_range);
+        }
+        GraphType::Ring => {
+            plan.connections = ring_graph(&peer_ids);
+        }
+        GraphType::Line => {
+            plan.connections = line_graph(&peer_ids);
+        }
+        GraphType::Star => {
+            let hub = match &cli.star_hub {
+                Some(short_id) => peer_ids
+                    .iter()
+                    .find(|p| short_peer_id(p) == *short_id)
+                    .ok_or_else(|| format!("Star hub {} is not a peer", short_id))?,
+                None => peer_ids
+                    .iter()
+                    .min()
+                    .ok_or("Need at least one peer for star")?,
+            };
+            info!("--> star graph with hub {}", hub);
+            plan.connections = star_graph(&peer_ids, hub);
+            plan.graph_params.star_hub = Some(hub.clone());
+        }
+        GraphType::Islands => {
+            if cli.cluster_graph != GraphType::Complete && cli.cluster_graph != GraphType::LAModel {
+                return Err("Islands cluster graph must be complete or la-model".into());
+            }
+            if cli.bridges_per_cluster == 0 && cli.clusters > 1 {
+                return Err("Need at least one bridge per cluster to connect islands".into());
+            }
+            let islands = islands_graph(
+                &mut rng,
+                &peer_ids,
+                cli.clusters,
+                cli.bridges_per_cluster,
+                cli.cluster_graph,
+            );
+            for (i, cluster) in islands.clusters.iter().enumerate() {
+                for p in cluster {
+                    groups.insert(p.clone(), format!("cluster{}", i));
+                }
+            }
+            for p in islands.bridges {
+                roles.insert(p, PeerRole::Bridge);
+            }
+            plan.connections = islands.graph;
+            plan.graph_params.clusters = Some(islands.clusters.len());
+            plan.graph_params.bridges_per_cluster = Some(cli.bridges_per_cluster);
+            plan.graph_params.cluster_graph = Some(cli.cluster_graph);
+        }
+        GraphType::KConnected => {
+            if cli.connectivity == 0 {
+                return Err("Connectivity must be at least 1".into());
+            }
+            plan.connections = harary_graph(&peer_ids, cli.connectivity);
+            plan.graph_params.connectivity = Some(cli.connectivity);
+        }
+        GraphType::Hierarchy => {
+            if cli.tier_fanout.is_empty() || cli.tier_fanout.iter().any(|f| *f < 2) {
+                return Err("Tier fan-outs must be at least 2".into());
+            }
+            let tiered = hierarchy_graph(&peer_ids, &cli.tier_fanout);
+            for (i, team) in tiered.teams.iter().enumerate() {
+                for p in team {
+                    groups.insert(p.clone(), format!("team{}", i));
+                }
+            }
+            tiers = tiered.tiers;
+            plan.connections = tiered.graph;
+            plan.graph_params.tier_fanout = Some(cli.tier_fanout.clone());
+        }
+        GraphType::Gateway => {
+            if cli.gateways == 0 || cli.gateway_uplinks == 0 {
+                return Err("Need at least one gateway and one uplink per edge peer".into());
+            }
+            let (graph, gateways) = gateway_graph(&peer_ids, cli.gateways, cli.gateway_uplinks);
+            for p in &peer_ids {
+                roles.insert(p.clone(), PeerRole::Edge);
+            }
+            for p in gateways {
+                roles.insert(p, PeerRole::Gateway);
+            }
+            plan.connections = graph;
+            plan.graph_params.gateways = Some(cli.gateways);
+            plan.graph_params.gateway_uplinks = Some(cli.gateway_uplinks);
+        }
+        GraphType::RandomWaypoint => {
+            if !(cli.area_size > 0.0 && cli.radio_range > 0.0) {
+                return Err("Area size and radio range must be positive".into());
+            }
+            if !(0.0 < cli.min_speed && cli.min_speed <= cli.max_speed) {
+                return Err("Speeds must be positive, with min speed at most max speed".into());
+            }
+            if cli.mobility_tick_msec == 0 {
+                return Err("Mobility tick must be at least 1 msec".into());
+            }
+            let model = RandomWaypoint {
+                area_size: cli.area_size,
+                min_speed: cli.min_speed,
+                max_speed: cli.max_speed,
+                pause_msec: cli.pause_msec,
+            };
+            let duration_msec = cli.test_duration_sec as u64 * 1000;
+            let samples = model.sample(&mut rng, &peer_ids, cli.mobility_tick_msec, duration_msec);
+            plan.epochs =
+                mobility_epochs(&peer_ids, &samples, cli.mobility_tick_msec, cli.radio_range);
+            info!(
+                "--> random waypoint mobility: {} topology epochs",
+                plan.epochs.len()
+            );
+            plan.connections = plan
+                .epochs
+                .first()
+                .ok_or("Mobility model gave no topology epochs")?
+                .connections
+                .clone();
+            positions = samples.first().cloned();
+            plan.graph_params.area_size = Some(cli.area_size);
+            plan.graph_params.radio_range = Some(cli.radio_range);
+            plan.graph_params.min_speed = Some(cli.min_speed);
+            plan.graph_params.max_speed = Some(cli.max_speed);
+            plan.graph_params.pause_msec = Some(cli.pause_msec);
+            plan.graph_params.mobility_tick_msec = Some(cli.mobility_tick_msec);
+        }
+        GraphType::Trace => {
+            let duration_msec = cli.test_duration_sec as u64 * 1000;
+            let start_msec = (cli.trace_start_sec * 1000.0) as u64;
+            plan.epochs = trace_epochs(trace.unwrap(), &peer_ids, start_msec, duration_msec)?;
+            info!("--> contact trace: {} topology epochs", plan.epochs.len());
+            plan.connections = plan
+                .epochs
+                .first()
+                .ok_or("Contact trace gave no topology epochs")?
+                .connections
+                .clone();
+            plan.graph_params.graph_file = cli.graph_file.clone();
+            plan.graph_params.trace_start_sec = Some(cli.trace_start_sec);
+        }
+        GraphType::DataMule => {
+            if cli.cluster_graph != GraphType::Complete && cli.cluster_graph != GraphType::LAModel {
+                return Err("Data mule cluster graph must be complete or la-model".into());
+            }
+            if cli.clusters < 2 {
+                return Err("Need at least two clusters for mules to carry records between".into());
+            }
+            if cli.mules == 0 || cli.mules + cli.clusters > peer_ids.len() {
+                return Err(format!(
+                    "Need at least one mule, and a peer for each of {} clusters besides the {} \
+                     mule(s), but only {} peers joined",
+                    cli.clusters,
+                    cli.mules,
+                    peer_ids.len()
+                )
+                .into());
+            }
+            // Checked in msec, since a shorter dwell rounds down to 0
+            let dwell_msec = (cli.mule_dwell_sec * 1000.0) as u64;
+            if !(dwell_msec > 0 && cli.mule_transit_sec >= 0.0) {
+                return Err("Mule dwell must be at least 1 msec and transit not negative".into());
+            }
+            // The first peers are the mules, as with gateways
+            let (mules, rest) = peer_ids.split_at(cli.mules);
+            let clusters = split_clusters(rest, cli.clusters);
+            let mut base = cluster_graphs(&mut rng, &clusters, cli.cluster_graph);
+            for (i, cluster) in clusters.iter().enumerate() {
+                for p in cluster {
+                    groups.insert(p.clone(), format!("cluster{}", i));
+                }
+            }
+            for m in mules {
+                base.add_vertex(m);
+                roles.insert(m.clone(), PeerRole::Mule);
+            }
+            let duration_msec = cli.test_duration_sec as u64 * 1000;
+            let visits = mule_visits(
+                mules,
+                clusters.len(),
+                dwell_msec,
+                (cli.mule_transit_sec * 1000.0) as u64,
+                duration_msec,
+            );
+            let schedule = MuleSchedule { clusters, visits };
+            plan.epochs = mule_epochs(&base, &schedule);
+            info!(
+                "--> {} mule(s) visiting {} clusters: {} visits, {} topology epochs",
+                mules.len(),
+                schedule.clusters.len(),
+                schedule.visits.len(),
+                plan.epochs.len()
+            );
+            plan.connections = plan
+                .epochs
+                .first()
+                .ok_or("Mule schedule gave no topology epochs")?
+                .connections
+                .clone();
+            plan.graph_params.clusters = Some(schedule.clusters.len());
+            plan.graph_params.cluster_graph = Some(cli.cluster_graph);
+            plan.graph_params.mules = Some(cli.mules);
+            plan.graph_params.mule_dwell_sec = Some(cli.mule_dwell_sec);
+            plan.graph_params.mule_transit_sec = Some(cli.mule_transit_sec);
+            plan.mules = Some(schedule);
+        }
+        GraphType::File => {
+            plan.connections = map_labels(graph_file.unwrap(), &peer_ids)?;
+            plan.graph_params.graph_file = cli.graph_file.clone();
+        }
+    }
+    // Post-passes apply to each epoch's graph, if there are epochs
+    if let Some(max_degree) = cli.max_degree {
+        if max_degree == 0 {
+            return Err("Max degree must be at least 1".into());
+        }
+        let graphs: Vec<&mut PeerGraph> = if plan.epochs.is_empty() {
+            vec![&mut plan.connections]
+        } else {
+            plan.epochs.iter_mut().map(|e| &mut e.connections).collect()
+        };
+        // Any peers left over the limit are reported by validate_plan
+        for graph in graphs {
+            enforce_max_degree(graph, max_degree);
+        }
+        plan.graph_params.max_degree = Some(max_degree);
+    }
+    // Epochs share one orientation, which the link flaps below rely on
+    if plan.epochs.is_empty() {
+        orient_edges(&mut rng, &mut plan.connections, cli.orientation);
+    } else {
+        orient_epochs(&mut rng, &mut plan.epochs, cli.orientation);
+    }
+    let duration_msec = cli.test_duration_sec as u64 * 1000;
+    if let Some(k) = cli.partitions {
+        if k < 2 {
+            return Err("Need at least two partitions".into());
+        }
+        let schedule = PartitionSchedule {
+            split_offset_msec: cli
+                .split_at_sec
+                .map_or(duration_msec / 3, |s| s as u64 * 1000),
+            heal_offset_msec: cli
+                .heal_at_sec
+                .map_or(2 * duration_msec / 3, |s| s as u64 * 1000),
+            partitions: split_clusters(&peer_ids, k),
+        };
+        info!(
+            "--> {} partitions from {} to {} msec",
+            schedule.partitions.len(),
+            schedule.split_offset_msec,
+            schedule.heal_offset_msec
+        );
+        plan.epochs = partition_epochs(&plan.connections, &plan.epochs, &schedule);
+        plan.partition = Some(schedule);
+    }
+    if let Some(first) = plan.epochs.first() {
+        plan.connections = first.connections.clone();
+    }
+    if let Some(rate) = cli.churn_rate {
+        if !(rate > 0.0 && cli.churn_downtime_sec > 0.0) {
+            return Err("Churn rate and downtime must be positive".into());
+        }
+        let mean_downtime_msec = cli.churn_downtime_sec * 1000.0;
+        plan.churn = random_churn(&mut rng, &peer_ids, rate, mean_downtime_msec, duration_msec);
+    } else if let Some(churn) = churn_file {
+        plan.churn = map_churn(churn, &peer_ids)?;
+    }
+    if !plan.churn.is_empty() {
+        info!(
+            "--> churn: {} peer(s) go offline, {} times in all",
+            plan.churn.len(),
+            plan.churn.values().map(|w| w.len()).sum::<usize>()
+        );
+    }
+    if let Some(fraction) = cli.flap_fraction {
+        // Checked in msec, since shorter times round down to 0
+        let up_msec = (cli.flap_up_sec * 1000.0) as u64;
+        let down_msec = (cli.flap_down_sec * 1000.0) as u64;
+        if !((0.0..=1.0).contains(&fraction) && up_msec > 0 && down_msec > 0) {
+            return Err("Flap fraction must be in [0, 1] and flap times at least 1 msec".into());
+        }
+        // Links of every epoch can flap, not just those present at the start
+        let mut links = plan.connections.clone();
+        for e in &plan.epochs {
+            links.merge(e.connections.clone());
+        }
+        plan.link_flaps = link_flaps(
+            &mut rng,
+            &links,
+            fraction,
+            cli.flap_mode,
+            up_msec,
+            down_msec,
+            duration_msec,
+        );
+        info!(
+            "--> {} link(s) flap, going down {} times in all",
+            plan.link_flaps.len(),
+            plan.link_flaps.iter().map(|f| f.down.len()).sum::<usize>()
+        );
     }
-    plan.test_duration_sec = duration_sec;
-    plan
+    plan.graph_params.orientation = cli.orientation;
+    for p in plan.peers.iter_mut() {
+        if let Some(positions) = &positions {
+            p.position = positions.get(&p.peer_id).copied();
+        }
+        p.group = groups.remove(&p.peer_id);
+        p.role = roles.remove(&p.peer_id);
+        p.tier = tiers.remove(&p.peer_id);
+    }
+    plan.test_duration_sec = cli.test_duration_sec;
+    Ok(plan)
+}
+
+// Load the graph file for --connection-graph=file up front, so a bad file fails before we wait
+// for peers
+fn load_cli_graph_file(cli: &Cli) -> Result<Option<LabeledGraph>, Box<dyn Error>> {
+    if cli.connection_graph != GraphType::File {
+        return Ok(None);
+    }
+    let path = cli
+        .graph_file
+        .as_ref()
+        .ok_or("--connection-graph=file requires --graph-file")?;
+    let graph = load_graph_file(Path::new(path))?;
+    if graph.nodes.len() != cli.min_peers {
+        return Err(format!(
+            "Graph file {} has {} nodes, but --min-peers is {}",
+            path,
+            graph.nodes.len(),
+            cli.min_peers
+        )
+        .into());
+    }
+    info!(
+        "--> loaded graph file {}: {} nodes, {} edges",
+        path,
+        graph.nodes.len(),
+        graph.edges.len()
+    );
+    Ok(Some(graph))
+}
+
+// Load the contact trace for --connection-graph=trace up front, as for graph files
+fn load_cli_trace(cli: &Cli) -> Result<Option<ContactTrace>, Box<dyn Error>> {
+    if cli.connection_graph != GraphType::Trace {
+        return Ok(None);
+    }
+    if !cli.trace_start_sec.is_finite() || cli.trace_start_sec < 0.0 {
+        return Err("Trace start must not be negative".into());
+    }
+    let path = cli
+        .graph_file
+        .as_ref()
+        .ok_or("--connection-graph=trace requires --graph-file")?;
+    let trace = load_contact_trace(Path::new(path))?;
+    info!(
+        "--> loaded contact trace {}: {} nodes, {} contacts",
+        path,
+        trace.nodes.len(),
+        trace.contacts.len()
+    );
+    Ok(Some(trace))
+}
+
+// Path for an output file describing this run's connection graph, e.g.
+// <output_dir>/conn-graph-la-model-20.dot
+fn graph_output_path(cli: &Cli, prefix: &str, ext: &str) -> PathBuf {
+    Path::new(&cli.output_dir).join(format!(
+        "{}-{}-{}.{}",
+        prefix,
+        &cli.connection_graph.to_possible_value().unwrap().get_name(),
+        cli.min_peers,
+        ext
+    ))
+}
+
+fn write_graph_outputs(cli: &Cli, plan: &ExecutionPlan) -> Result<(), Box<dyn Error>> {
+    let positions: HashMap<PeerId, Position> = plan
+        .peers
+        .iter()
+        .filter_map(|p| Some((p.peer_id.clone(), p.position?)))
+        .collect();
+    let mut attrs = GraphAttributes::new();
+    if !positions.is_empty() {
+        attrs.pin_positions(&positions);
+    }
+    let dot = export::to_dot(&plan.connections, &attrs);
+    File::create(graph_output_path(cli, "conn-graph", "dot"))?.write_all(dot.as_bytes())?;
+
+    if !positions.is_empty() {
+        let f = File::create(graph_output_path(cli, "peer-positions", "json"))?;
+        serde_json::to_writer_pretty(f, &positions)?;
+    }
+
+    let stats = graph_stats(&plan.connections);
+    info!(
+        "--> graph stats: {} edges, {} component(s), diameter {}, avg. path {:.2}, \
+         connectivity {}",
+        stats.num_edges,
+        stats.component_sizes.len(),
+        stats.diameter,
+        stats.avg_shortest_path,
+        stats.vertex_connectivity
+    );
+    let f = File::create(graph_output_path(cli, "graph-stats", "json"))?;
+    serde_json::to_writer_pretty(f, &stats)?;
+    Ok(())
+}
+
+// Write the connection graph in each export format, annotated with what we know about each peer
+// and link at the end of the run
+fn write_topology_exports(
+    cli: &Cli,
+    plan: &ExecutionPlan,
+    hbp: &HeartbeatProcessor,
+    reports: &HashMap<PeerId, PeerReport>,
+) -> Result<(), Box<dyn Error>> {
+    let graph = &plan.connections;
+    let mut attrs = GraphAttributes::from_peers(&plan.peers);
+    attrs.set_links(graph, &plan.peers);
+    for p in hbp.peer_set.lock().unwrap().iter() {
+        attrs.set_node(&p.peer_id, "state", p.state.to_string());
+    }
+    for (peer_id, (_, age)) in hbp.beat_age.lock().unwrap().iter() {
+        attrs.set_node(peer_id, "heartbeat_age_with_skew_msec", *age);
+    }
+    for (peer_id, report) in reports {
+        let latency = &report.message_latency;
+        if latency.num_events > 0 {
+            attrs.set_node(peer_id, "message_latency_avg_msec", latency.avg_msec);
+            attrs.set_node(peer_id, "message_latency_max_msec", latency.max_msec);
+        }
+        let split = [
+            ("group_latency_avg_msec", &report.group_latency),
+            ("cross_group_latency_avg_msec", &report.cross_group_latency),
+        ];
+        for (key, latency) in split {
+            if let Some(l) = latency.as_ref().filter(|l| l.num_events > 0) {
+                attrs.set_node(peer_id, key, l.avg_msec);
+            }
+        }
+    }
+    File::create(graph_output_path(cli, "topology", "dot"))?
+        .write_all(export::to_dot(graph, &attrs).as_bytes())?;
+    File::create(graph_output_path(cli, "topology", "graphml"))?
+        .write_all(export::to_graphml(graph, &attrs).as_bytes())?;
+    let f = File::create(graph_output_path(cli, "topology", "json"))?;
+    serde_json::to_writer_pretty(f, &export::to_json_adjacency(graph, &attrs))?;
+    File::create(graph_output_path(cli, "topology", "mmd"))?
+        .write_all(export::to_mermaid(graph, &attrs).as_bytes())?;
+    Ok(())
 }
 
 fn main() -> Result<(), Box<dyn Error>> {
@@ -329,6 +1094,12 @@ fn main() -> Result<(), Box<dyn Error>> {
     debug!("Args {:?}", cli);
     // Fail fast if output directory doesn't exist
     let _ = std::fs::read_dir(&cli.output_dir).expect("Exiting: output dir not found.");
+    let graph_file = load_cli_graph_file(&cli)?;
+    let trace = load_cli_trace(&cli)?;
+    let churn_file = match &cli.churn_file {
+        Some(path) => Some(load_churn_file(Path::new(path))?),
+        None => None,
+    };
     let mut ctx = CoordinatorContext {
         ditto: make_ditto()?,
         coord_collection: None,
@@ -337,6 +1108,8 @@ fn main() -> Result<(), Box<dyn Error>> {
         hb_doc_id: None,
         hb_processor: None,
         hb_observer: None,
+        report_collection: None,
+        report_subscription: None,
         peers: Arc::new(Mutex::new(HashSet::new())),
         presence: None,
     };
@@ -349,7 +1122,17 @@ fn main() -> Result<(), Box<dyn Error>> {
     wait_for_quorum(&mut ctx, &cli.coord_collection, cli.min_peers)?;
 
     info!("-> got quorum, writing test plan..");
-    let plan = generate_plan(&ctx, cli.test_duration_sec, cli.connection_graph);
+    let plan = generate_plan(
+        &ctx,
+        &cli,
+        graph_file.as_ref(),
+        trace.as_ref(),
+        churn_file.as_ref(),
+    )?;
+    if let Err(e) = validate_plan(&plan) {
+        return Err(format!("Refusing to publish invalid test plan: {}", e).into());
+    }
+    init_report_collection(&mut ctx, &plan.report_collection_name)?;
     debug!(
         "--> peer_doc_id: {} === {:?}",
         plan.peer_doc_id
@@ -370,21 +1153,18 @@ fn main() -> Result<(), Box<dyn Error>> {
     set_coord_info_plan_start(ccollection, cdoc_id, system_time_msec() + start_in_sec * 1000)?;
 
     info!("-> waiting for peers to start Running..");
-    wait_for_peer_state(hbp, Running, cli.min_peers)?;
+    // Peers the churn schedule takes offline right away say so first
+    wait_for_peer_states(hbp, vec![Running, Offline], cli.min_peers)?;
 
     info!("--> writing connection graph to conn-graph.dot");
-    let dot_outfile = Path::new(&cli.output_dir).join(format!(
-        "conn-graph-{}-{}.dot",
-        &cli.connection_graph.to_possible_value().unwrap().get_name(),
-        cli.min_peers
-    ));
-    File::create(dot_outfile)
-        .unwrap()
-        .write_all(plan.connections.to_dot().as_bytes())
-        .unwrap();
+    write_graph_outputs(&cli, &plan)?;
 
     info!("-> waiting for peers to finish running..");
     wait_for_peer_states(hbp, vec![Reporting, Shutdown], cli.min_peers)?;
 
+    info!("-> collecting peer reports");
+    let reports = collect_reports(&ctx, cli.min_peers)?;
+    info!("-> writing topology exports");
+    write_topology_exports(&cli, &plan, hbp, &reports)?;
     Ok(())
 }
@@ -1,7 +1,7 @@
 use log::*;
 use std::{
     cmp,
-    collections::HashMap,
+    collections::{HashMap, HashSet},
     error::Error,
     sync::{Arc, Mutex},
 };
@@ -18,12 +18,157 @@ pub struct PeerConsumer {
     last_ts_idx_by_peer: HashMap<PeerId, (u64, u32)>,
     msg_latency: LatencyStats,
     msg_latency_total: u64,
+    // Group of each peer in the plan that has one, and latency split by same / other group
+    groups: HashMap<PeerId, String>,
+    group_latency: LatencyStats,
+    group_latency_total: u64,
+    cross_group_latency: LatencyStats,
+    cross_group_latency_total: u64,
+    // Start time (msec) of each topology epoch, and latency split by the epoch a record was
+    // written in. Empty for a fixed mesh.
+    epoch_starts: Vec<u64>,
+    epoch_latency: Vec<LatencyStats>,
+    epoch_latency_total: Vec<u64>,
+    epoch_peers: Vec<HashSet<PeerId>>,
+    resync: Option<ResyncTracker>,
+    seq_gaps: SeqGaps,
+    // Our scheduled downtime, and how we catch up with other peers after each window
+    downtime: Vec<(DowntimeWindow, ResyncTracker)>,
+    mule_delivery: Option<MuleTracker>,
     // To keep subscription alive as needed
     #[allow(dead_code)]
     subscription: Subscription,
     pub live_query: Option<LiveQuery>,
 }
 
+// Tracks how long after a partition heals, or we come back online, we catch up with each of the
+// other peers. Once we get a record a peer wrote after the heal, there is nothing older left to
+// sync from it, so we were caught up as soon as we got the newest record it wrote before the heal.
+// Records that reached us before the heal don't count: if none arrive after it (the peer's log
+// may have wrapped past them), we caught up with the first record written after the heal.
+struct ResyncTracker {
+    heal_time: u64,
+    others: HashSet<PeerId>,
+    // Timestamp and arrival time of the newest record from each other peer written before the
+    // heal and received after it
+    newest_before_heal: HashMap<PeerId, (u64, u64)>,
+    caught_up: HashSet<PeerId>,
+    latency: LatencyStats,
+    latency_total: u64,
+}
+
+impl ResyncTracker {
+    fn new(heal_time: u64, others: HashSet<PeerId>) -> Self {
+        Self {
+            heal_time,
+            others,
+            newest_before_heal: HashMap::new(),
+            caught_up: HashSet::new(),
+            latency: LatencyStats::new(),
+            latency_total: 0,
+        }
+    }
+
+    fn record(&mut self, peer_id: &PeerId, timestamp: u64, now: u64) {
+        if !self.others.contains(peer_id) || self.caught_up.contains(peer_id) {
+            return;
+        }
+        if timestamp < self.heal_time {
+            if now < self.heal_time {
+                return;
+            }
+            let newest = self
+                .newest_before_heal
+                .entry(peer_id.clone())
+                .or_insert((timestamp, now));
+            if timestamp > newest.0 {
+                *newest = (timestamp, now);
+            }
+        } else {
+            let arrived = self
+                .newest_before_heal
+                .get(peer_id)
+                .map_or(now, |(_, arrived)| *arrived);
+            // Only clock skew between hosts can put now before the heal here
+            let latency = arrived.saturating_sub(self.heal_time);
+            record_latency(&mut self.latency, &mut self.latency_total, latency);
+            self.caught_up.insert(peer_id.clone());
+        }
+    }
+
+    fn stats(&self) -> LatencyStats {
+        let mut stats = self.latency.clone();
+        stats.distinct_peers = self.others.len();
+        stats
+    }
+}
+
+// Tracks how long after a mule arrives at our cluster records from other clusters reach us.
+// Mules are the only way across, so whatever arrives from another cluster came with the latest
+// mule to arrive.
+struct MuleTracker {
+    arrivals: Vec<u64>,
+    others: HashSet<PeerId>,
+    latency: LatencyStats,
+    latency_total: u64,
+}
+
+impl MuleTracker {
+    fn new(mut arrivals: Vec<u64>, others: HashSet<PeerId>) -> Self {
+        arrivals.sort_unstable();
+        Self {
+            arrivals,
+            others,
+            latency: LatencyStats::new(),
+            latency_total: 0,
+        }
+    }
+
+    fn record(&mut self, peer_id: &PeerId, now: u64) {
+        if !self.others.contains(peer_id) {
+            return;
+        }
+        let i = self.arrivals.partition_point(|t| *t <= now);
+        if i > 0 {
+            let latency = now - self.arrivals[i - 1];
+            record_latency(&mut self.latency, &mut self.latency_total, latency);
+        }
+    }
+
+    fn stats(&self) -> LatencyStats {
+        let mut stats = self.latency.clone();
+        stats.distinct_peers = self.others.len();
+        stats
+    }
+}
+
+// Highest sequence number and number of records we got from each peer. Any difference means
+// records were overwritten in the peer's log before they reached us.
+#[derive(Default)]
+struct SeqGaps {
+    by_peer: HashMap<PeerId, (u64, u64)>,
+}
+
+impl SeqGaps {
+    fn record(&mut self, peer_id: &PeerId, seq: u64) {
+        // Peers that don't number their records write 0
+        if seq == 0 {
+            return;
+        }
+        let (highest, count) = self.by_peer.entry(peer_id.clone()).or_insert((0, 0));
+        *highest = cmp::max(*highest, seq);
+        *count += 1;
+    }
+
+    fn missed<'a>(&self, peers: impl IntoIterator<Item = &'a PeerId>) -> u64 {
+        peers
+            .into_iter()
+            .filter_map(|p| self.by_peer.get(p))
+            .map(|(highest, count)| highest.saturating_sub(*count))
+            .sum()
+    }
+}
+
 fn incr_wrap(i: u32, max: u32) -> u32 {
     let mut r = i + 1;
     if r > max {
@@ -32,13 +177,43 @@ fn incr_wrap(i: u32, max: u32) -> u32 {
     r
 }
 
+fn record_latency(stats: &mut LatencyStats, total: &mut u64, latency: u64) {
+    *total += latency;
+    stats.num_events += 1;
+    stats.min_msec = cmp::min(stats.min_msec, latency);
+    stats.max_msec = cmp::max(stats.max_msec, latency);
+    stats.avg_msec = *total / stats.num_events;
+}
+
 impl PeerConsumer {
-    fn new(local_id: PeerId, subscription: Subscription) -> Self {
+    fn new(
+        local_id: PeerId,
+        groups: HashMap<PeerId, String>,
+        epoch_starts: Vec<u64>,
+        resync: Option<ResyncTracker>,
+        downtime: Vec<(DowntimeWindow, ResyncTracker)>,
+        mule_delivery: Option<MuleTracker>,
+        subscription: Subscription,
+    ) -> Self {
+        let n = epoch_starts.len();
         Self {
             local_id,
             last_ts_idx_by_peer: HashMap::new(),
             msg_latency: LatencyStats::new(),
             msg_latency_total: 0,
+            groups,
+            group_latency: LatencyStats::new(),
+            group_latency_total: 0,
+            cross_group_latency: LatencyStats::new(),
+            cross_group_latency_total: 0,
+            epoch_starts,
+            epoch_latency: vec![LatencyStats::new(); n],
+            epoch_latency_total: vec![0; n],
+            epoch_peers: vec![HashSet::new(); n],
+            resync,
+            seq_gaps: SeqGaps::default(),
+            downtime,
+            mule_delivery,
             subscription,
             live_query: None,
         }
@@ -56,8 +231,17 @@ impl PeerConsumer {
         self.last_ts_idx_by_peer.insert(peer_id, (ts, i));
     }
 
+    fn local_group(&self) -> Option<&String> {
+        self.groups.get(&self.local_id)
+    }
+
+    fn in_local_group(&self, peer_id: &PeerId) -> bool {
+        self.groups.get(peer_id) == self.local_group()
+    }
+
     fn process_peer(&mut self, id: PeerId, pl: &PeerLog) {
         let now = system_time_msec();
+        let same_group = self.in_local_group(&id);
         let (mut ts, mut i) = self.get_ts_idx(&id);
         debug!("--> process_peer {} w/ log len {}", id, pl.log.len());
         loop {
@@ -68,11 +252,38 @@ impl PeerConsumer {
             }
             let r = rec.unwrap();
             let latency = now - r.timestamp;
-            self.msg_latency_total += latency;
-            self.msg_latency.num_events += 1;
-            self.msg_latency.min_msec = cmp::min(self.msg_latency.min_msec, latency);
-            self.msg_latency.max_msec = cmp::max(self.msg_latency.max_msec, latency);
-            self.msg_latency.avg_msec = self.msg_latency_total / self.msg_latency.num_events;
+            record_latency(&mut self.msg_latency, &mut self.msg_latency_total, latency);
+            if same_group {
+                record_latency(
+                    &mut self.group_latency,
+                    &mut self.group_latency_total,
+                    latency,
+                );
+            } else {
+                record_latency(
+                    &mut self.cross_group_latency,
+                    &mut self.cross_group_latency_total,
+                    latency,
+                );
+            }
+            if let Some(e) = self.epoch_starts.iter().rposition(|t| *t <= r.timestamp) {
+                record_latency(
+                    &mut self.epoch_latency[e],
+                    &mut self.epoch_latency_total[e],
+                    latency,
+                );
+                self.epoch_peers[e].insert(id.clone());
+            }
+            self.seq_gaps.record(&id, r.seq);
+            if let Some(resync) = self.resync.as_mut() {
+                resync.record(&id, r.timestamp, now);
+            }
+            for (_, tracker) in self.downtime.iter_mut() {
+                tracker.record(&id, r.timestamp, now);
+            }
+            if let Some(mules) = self.mule_delivery.as_mut() {
+                mules.record(&id, now);
+            }
             debug!("--> got peer record {:?} w/ latency {}", r, latency);
             i = incr_wrap(i, PEER_LOG_SIZE-1);
             ts = r.timestamp
@@ -95,6 +306,74 @@ impl PeerConsumer {
         stats.distinct_peers = self.last_ts_idx_by_peer.len();
         stats
     }
+
+    // Latency from peers in our group, and from peers in other groups. None if we have no group.
+    pub fn get_group_latency(&self) -> (Option<LatencyStats>, Option<LatencyStats>) {
+        if self.local_group().is_none() {
+            return (None, None);
+        }
+        let mut same = self.group_latency.clone();
+        let mut cross = self.cross_group_latency.clone();
+        same.distinct_peers = self
+            .last_ts_idx_by_peer
+            .keys()
+            .filter(|p| self.in_local_group(p))
+            .count();
+        cross.distinct_peers = self.last_ts_idx_by_peer.len() - same.distinct_peers;
+        (Some(same), Some(cross))
+    }
+
+    pub fn get_resync_latency(&self) -> Option<LatencyStats> {
+        self.resync.as_ref().map(|r| r.stats())
+    }
+
+    pub fn get_resync_missed_records(&self) -> Option<u64> {
+        self.resync
+            .as_ref()
+            .map(|r| self.seq_gaps.missed(&r.others))
+    }
+
+    pub fn get_mule_delivery(&self) -> Option<LatencyStats> {
+        self.mule_delivery.as_ref().map(|m| m.stats())
+    }
+
+    pub fn get_mule_missed_records(&self) -> Option<u64> {
+        self.mule_delivery
+            .as_ref()
+            .map(|m| self.seq_gaps.missed(&m.others))
+    }
+
+    pub fn get_downtime(&self) -> Option<Vec<DowntimeReport>> {
+        if self.downtime.is_empty() {
+            return None;
+        }
+        let reports = self
+            .downtime
+            .iter()
+            .map(|(window, tracker)| DowntimeReport {
+                window: *window,
+                catch_up: tracker.stats(),
+            })
+            .collect();
+        Some(reports)
+    }
+
+    // Latency by topology epoch, None for a fixed mesh
+    pub fn get_epoch_latency(&self) -> Option<Vec<LatencyStats>> {
+        if self.epoch_starts.is_empty() {
+            return None;
+        }
+        let stats = self
+            .epoch_latency
+            .iter()
+            .zip(&self.epoch_peers)
+            .map(|(s, peers)| LatencyStats {
+                distinct_peers: peers.len(),
+                ..s.clone()
+            })
+            .collect();
+        Some(stats)
+    }
 }
 
 pub fn consumer_create_collection(pctx: &PeerContext) -> Result<Collection, Box<dyn Error>> {
@@ -125,7 +404,71 @@ pub fn consumer_start(pctx: &PeerContext) -> Result<PeerConsumerRef, Box<dyn Err
         peer_doc_id.to_query_compatible(StringPrimitiveFormat::WithoutQuotes)
     );
 
-    let _consumer = Arc::new(Mutex::new(PeerConsumer::new(pctx.id.clone(), query.subscribe())));
+    let groups = plan
+        .peers
+        .iter()
+        .filter_map(|p| Some((p.peer_id.clone(), p.group.clone()?)))
+        .collect();
+    let epoch_starts = plan
+        .epochs
+        .iter()
+        .map(|e| plan.start_time + e.offset_msec)
+        .collect();
+    let resync = plan.partition.as_ref().map(|p| {
+        let ours = p.partition_of(&pctx.id);
+        let others = plan
+            .peers
+            .iter()
+            .filter(|q| p.partition_of(&q.peer_id) != ours)
+            .map(|q| q.peer_id.clone())
+            .collect();
+        ResyncTracker::new(plan.start_time + p.heal_offset_msec, others)
+    });
+    let others: HashSet<PeerId> = plan
+        .peers
+        .iter()
+        .map(|p| p.peer_id.clone())
+        .filter(|p| *p != pctx.id)
+        .collect();
+    // Catch-up is timed from coming back online. Records we held before going offline arrived
+    // before then, so the tracker ignores them.
+    let downtime = plan
+        .churn
+        .get(&pctx.id)
+        .into_iter()
+        .flatten()
+        .map(|w| {
+            let up_time = plan.start_time + w.up_offset_msec;
+            (*w, ResyncTracker::new(up_time, others.clone()))
+        })
+        .collect();
+    // Only peers in a cluster wait for mules
+    let mule_delivery = plan.mules.as_ref().and_then(|m| {
+        let ours = m.cluster_of(&pctx.id)?;
+        let arrivals = m
+            .visits
+            .iter()
+            .filter(|v| v.cluster == ours)
+            .map(|v| plan.start_time + v.arrive_offset_msec)
+            .collect();
+        let others = m
+            .clusters
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| *i != ours)
+            .flat_map(|(_, c)| c.iter().cloned())
+            .collect();
+        Some(MuleTracker::new(arrivals, others))
+    });
+    let _consumer = Arc::new(Mutex::new(PeerConsumer::new(
+        pctx.id.clone(),
+        groups,
+        epoch_starts,
+        resync,
+        downtime,
+        mule_delivery,
+        query.subscribe(),
+    )));
     let consumer = _consumer.clone();
     let live_query = query
         .observe_local(move |doc: Option<BoxedDocument>, event| {
@@ -152,3 +495,71 @@ pub fn consumer_start(pctx: &PeerContext) -> Result<PeerConsumerRef, Box<dyn Err
     _consumer.lock().unwrap().live_query = Some(live_query);
     Ok(_consumer)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tracker(heal_time: u64) -> ResyncTracker {
+        let others = ["peer1_a1b2", "peer2_c3d4"]
+            .iter()
+            .map(|p| p.to_string())
+            .collect();
+        ResyncTracker::new(heal_time, others)
+    }
+
+    #[test]
+    fn test_resync_after_heal() {
+        let (a, b) = ("peer1_a1b2".to_string(), "peer2_c3d4".to_string());
+        let mut t = tracker(10_000);
+        // Written during the split, delivered after the heal
+        t.record(&a, 9_000, 10_500);
+        t.record(&a, 9_500, 11_200);
+        t.record(&a, 10_100, 11_300);
+        t.record(&a, 9_800, 12_000);
+        assert_eq!(t.stats().num_events, 1);
+        assert_eq!(t.stats().max_msec, 1_200);
+
+        // Every pre-heal record arrived before the heal, e.g. the log wrapped past the rest
+        t.record(&b, 2_000, 3_000);
+        t.record(&b, 8_000, 8_100);
+        t.record(&b, 10_200, 14_000);
+        let stats = t.stats();
+        assert_eq!(stats.num_events, 2);
+        assert_eq!((stats.min_msec, stats.max_msec), (1_200, 4_000));
+        assert_eq!(stats.distinct_peers, 2);
+    }
+
+    #[test]
+    fn test_seq_gaps() {
+        let (a, b) = ("peer1_a1b2".to_string(), "peer2_c3d4".to_string());
+        let mut gaps = SeqGaps::default();
+        // 5 to 20 were overwritten before we read a's log again
+        for seq in [1, 2, 3, 4, 21, 22] {
+            gaps.record(&a, seq);
+        }
+        gaps.record(&b, 0);
+        assert_eq!(gaps.missed([&a]), 16);
+        assert_eq!(gaps.missed([&b]), 0);
+        assert_eq!(gaps.missed([&a, &b]), 16);
+    }
+
+    #[test]
+    fn test_catch_up_after_downtime() {
+        let (a, b) = ("peer1_a1b2".to_string(), "peer2_c3d4".to_string());
+        // Offline from 5000 until 15000
+        let mut t = tracker(15_000);
+        // Held before going offline
+        t.record(&a, 1_000, 1_050);
+        t.record(&a, 4_900, 4_950);
+        t.record(&b, 4_800, 4_900);
+        assert_eq!(t.stats().num_events, 0);
+
+        // Only records written after we came back arrive later
+        t.record(&a, 15_500, 17_000);
+        t.record(&b, 16_000, 18_500);
+        let stats = t.stats();
+        assert_eq!(stats.num_events, 2);
+        assert_eq!((stats.min_msec, stats.max_msec), (2_000, 3_500));
+    }
+}
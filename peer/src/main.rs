@@ -69,7 +69,8 @@ fn make_ditto(device_name: &str) -> Result<Ditto, DittoError> {
 
 fn init_transport(pctx: &mut PeerContext, cli: &Cli) -> Result<(), Box<dyn Error>> {
     let mut config = TransportConfig::default();
-    config.peer_to_peer.lan.enabled = true;
+    // Off until we know the plan: LAN discovery would link peers the plan keeps apart
+    config.peer_to_peer.lan.enabled = false;
     // fail fast
     let _ip_addr: std::net::IpAddr = cli.coord_addr.parse()?;
     let coord_addr = format!("{}:{}", cli.coord_addr, cli.coord_port);
@@ -223,6 +224,10 @@ fn bootstrap_peer<'a>(pctx: &'a mut PeerContext, cli: &Cli) -> Result<(), Box<dy
             peer_id: pctx.id.clone(),
             peer_ip_addr: pctx.local_ip.clone(),
             peer_port: pctx.local_port,
+            position: None,
+            group: None,
+            role: None,
+            tier: None,
         },
         sent_at_msec: 0,
     };
@@ -309,8 +314,13 @@ fn wait_for_plan(
     Ok(())
 }
 
-fn connect_mesh(pctx: &PeerContext) -> Result<(), Box<dyn Error>> {
-    // Connect to all other peers in coord_info.
+// Connect to the peers we dial in the given graph, except those whose link is down. Starts over
+// from our initial transport config each time, so peers we dialed before are dropped.
+fn connect_mesh(
+    pctx: &PeerContext,
+    graph: &PeerGraph,
+    down_links: &HashSet<PeerId>,
+) -> Result<(), Box<dyn Error>> {
     let mut all_peers = pctx
         .transport_config
         .as_ref()
@@ -320,9 +330,16 @@ fn connect_mesh(pctx: &PeerContext) -> Result<(), Box<dyn Error>> {
         .clone();
     let n = all_peers.len();
     let plan = pctx.get_plan().unwrap();
-    let my_peers = plan.connections.nmap.get(&pctx.id);
-    for p in my_peers.unwrap() {
-        let peer_obj = plan.peers.iter().find(|x| x.peer_id == *p).unwrap();
+    let my_peers = graph
+        .nmap
+        .get(&pctx.id)
+        .ok_or_else(|| format!("Peer {} has no entry in the plan's connections", pctx.id))?;
+    for p in my_peers.iter().filter(|p| !down_links.contains(*p)) {
+        let peer_obj = plan
+            .peers
+            .iter()
+            .find(|x| x.peer_id == *p)
+            .ok_or_else(|| format!("Peer {} in connections is not in the plan", p))?;
         all_peers.insert(format!("{}:{}", peer_obj.peer_ip_addr, peer_obj.peer_port));
     }
     let m = all_peers.len();
@@ -334,12 +351,58 @@ fn connect_mesh(pctx: &PeerContext) -> Result<(), Box<dyn Error>> {
     Ok(())
 }
 
+// Scheduled changes to our connectivity during the test
+enum TestEvent {
+    Epoch(usize),
+    GoOffline,
+    GoOnline,
+    LinkDown(PeerId),
+    LinkUp(PeerId),
+}
+
+// Our events from the plan, as (offset msec after start_time, event) in time order
+fn test_events(plan: &ExecutionPlan, id: &PeerId) -> Vec<(u64, TestEvent)> {
+    let mut events: Vec<(u64, TestEvent)> = plan
+        .epochs
+        .iter()
+        .enumerate()
+        .skip(1)
+        .map(|(i, e)| (e.offset_msec, TestEvent::Epoch(i)))
+        .collect();
+    for w in plan.churn.get(id).into_iter().flatten() {
+        events.push((w.down_offset_msec, TestEvent::GoOffline));
+        events.push((w.up_offset_msec, TestEvent::GoOnline));
+    }
+    // We dial, so we drop and restore the link
+    for flap in plan.link_flaps.iter().filter(|f| f.dialer == *id) {
+        let p = &flap.listener;
+        for w in &flap.down {
+            events.push((w.down_offset_msec, TestEvent::LinkDown(p.clone())));
+            events.push((w.up_offset_msec, TestEvent::LinkUp(p.clone())));
+        }
+    }
+    events.sort_by_key(|(offset, _)| *offset);
+    events
+}
+
+fn sleep_until_msec(time_msec: u64) {
+    let now = system_time_msec();
+    if time_msec > now {
+        thread::sleep(Duration::from_millis(time_msec - now));
+    }
+}
+
 fn run_test(pctx: &mut PeerContext) -> Result<PeerReport, Box<dyn Error>> {
-    // connect to all other peers in coord_info
-    connect_mesh(pctx)?;
+    // connect to our peers in the first epoch's graph
+    let plan = pctx.get_plan().unwrap();
+    if plan.is_static() {
+        let config = pctx.transport_config.as_mut().unwrap();
+        config.peer_to_peer.lan.enabled = true;
+    }
+    let mut down_links: HashSet<PeerId> = HashSet::new();
+    connect_mesh(pctx, plan.epoch_connections(0), &down_links)?;
 
     // wait for start time
-    let plan = pctx.get_plan().unwrap();
     let start_time = plan.start_time;
     let now = system_time_msec();
     let wait_time;
@@ -367,12 +430,57 @@ fn run_test(pctx: &mut PeerContext) -> Result<PeerReport, Box<dyn Error>> {
 
     let _pthread = producer_start(producer.clone());
 
-    // wait for test duration
+    // wait for test duration, following the plan's changes to our connectivity
     info!(
         "--> Waiting {} sec for test duration",
         plan.test_duration_sec
     );
-    thread::sleep(Duration::from_secs(plan.test_duration_sec as u64));
+    let end_time = start_time + plan.test_duration_sec as u64 * 1000;
+    let mut online = true;
+    let mut epoch = 0;
+    for (offset, event) in test_events(&plan, &pctx.id) {
+        if start_time + offset >= end_time {
+            break;
+        }
+        sleep_until_msec(start_time + offset);
+        match event {
+            TestEvent::Epoch(i) => {
+                info!("--> Topology epoch {} at offset {} msec", i, offset);
+                epoch = i;
+                connect_mesh(pctx, plan.epoch_connections(epoch), &down_links)?;
+            }
+            TestEvent::LinkDown(p) => {
+                info!("--> Link to {} down at offset {} msec", p, offset);
+                down_links.insert(p);
+                connect_mesh(pctx, plan.epoch_connections(epoch), &down_links)?;
+            }
+            TestEvent::LinkUp(p) => {
+                info!("--> Link to {} up at offset {} msec", p, offset);
+                down_links.remove(&p);
+                connect_mesh(pctx, plan.epoch_connections(epoch), &down_links)?;
+            }
+            TestEvent::GoOffline => {
+                info!("--> Going offline at offset {} msec", offset);
+                // Let the coordinator know this is planned while we can still reach it
+                pctx.state_transition(Some(Running), Offline)?;
+                heartbeat_send(&mut pctx.hb_ctx.clone().unwrap());
+                pctx.ditto.stop_sync();
+                online = false;
+            }
+            TestEvent::GoOnline => {
+                info!("--> Back online at offset {} msec", offset);
+                pctx.ditto.start_sync()?;
+                pctx.state_transition(Some(Offline), Running)?;
+                online = true;
+            }
+        }
+    }
+    sleep_until_msec(end_time);
+    if !online {
+        // We still need sync to report our state to the coordinator
+        pctx.ditto.start_sync()?;
+        pctx.state_transition(Some(Offline), Running)?;
+    }
     debug!("--> Shutting down producer..");
     producer_stop(&producer);
 
@@ -382,10 +490,32 @@ fn run_test(pctx: &mut PeerContext) -> Result<PeerReport, Box<dyn Error>> {
     // Return test report
     let consumer = _consumer.lock().unwrap();
     let _stats = LatencyStats::new();
+    let me = plan.peers.iter().find(|p| p.peer_id == pctx.id);
+    let (group_latency, cross_group_latency) = consumer.get_group_latency();
     let report = PeerReport {
         message_latency: consumer.get_message_latency(),
         records_produced: msg_count,
+        group: me.and_then(|p| p.group.clone()),
+        role: me.and_then(|p| p.role),
+        tier: me.and_then(|p| p.tier),
+        group_latency,
+        cross_group_latency,
+        epoch_latency: consumer.get_epoch_latency(),
+        resync_latency: consumer.get_resync_latency(),
+        resync_missed_records: consumer.get_resync_missed_records(),
+        downtime: consumer.get_downtime(),
+        mule_delivery: consumer.get_mule_delivery(),
+        mule_missed_records: consumer.get_mule_missed_records(),
     };
+    // Publish the report so the coordinator can add it to its topology exports
+    let report_coll = pctx
+        .ditto
+        .store()
+        .collection(&plan.report_collection_name)?;
+    report_coll.upsert(ReportDoc {
+        peer_id: pctx.id.clone(),
+        report: report.clone(),
+    })?;
     pctx.state_transition(Some(Reporting), Shutdown)?;
     std::thread::sleep(std::time::Duration::from_secs(REPORT_PROPAGATION_SEC));
     Ok(report)
@@ -3,9 +3,11 @@ use std::thread::{self, JoinHandle};
 
 use common::default::*;
 use common::types::*;
+use common::util::*;
 use dittolive_ditto::prelude::*;
 use log::*;
-use rand::Rng;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
 
 #[derive(Clone)]
 pub struct ProducerCtx {
@@ -13,6 +15,7 @@ pub struct ProducerCtx {
     collection: Arc<Mutex<Collection>>,
     plan: ExecutionPlan,
     msg_index: i32,
+    seq: u64,
     pub finished: Arc<AtomicBool>,
 }
 
@@ -23,6 +26,7 @@ impl ProducerCtx {
             collection,
             plan,
             msg_index: -1,
+            seq: 0,
             finished: Arc::new(AtomicBool::new(false)),
         }
     }
@@ -41,8 +45,12 @@ impl ProducerCtx {
 pub fn producer_send(prod_ctx: &mut ProducerCtx) {
     // get next index for circulare peer log
     let next_index = prod_ctx.get_next_index();
+    prod_ctx.seq += 1;
     let hbc_lock = prod_ctx.collection.lock().unwrap();
-    let rec = PeerRecord::default();
+    let rec = PeerRecord {
+        seq: prod_ctx.seq,
+        ..Default::default()
+    };
     // TODO fill in rec.data to pad size as desired
     let rec_path = format!(
         "logs['{}']['log']['{}']",
@@ -79,10 +87,12 @@ pub fn producer_stop(prod_ctx: &ProducerCtx) {
 pub fn producer_loop(mut prod_ctx: ProducerCtx) -> Result<u64, std::io::Error> {
     // TODO timing / message rate, etc.
     let mut count: u64 = 0;
+    // Key our delays by short ID (device name), since the rest of the peer ID is random
+    let seed = derive_seed(prod_ctx.plan.seed, &short_peer_id(&prod_ctx.peer_id));
+    let mut rng = StdRng::seed_from_u64(seed);
     while !prod_ctx.finished.load(std::sync::atomic::Ordering::Relaxed) {
         producer_send(&mut prod_ctx);
         count += 1;
-        let mut rng = rand::thread_rng();
         let msec =
             rng.gen_range(prod_ctx.plan.min_msg_delay_msec..prod_ctx.plan.max_msg_delay_msec);
         std::thread::sleep(std::time::Duration::from_millis(msec as u64));
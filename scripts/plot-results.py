@@ -104,6 +104,8 @@ def graph_type_color(type: str) -> str:
         return 'blue'
     elif type == 'la-model':
         return 'green'
+    elif type == 'k-connected':
+        return 'orange'
     else:
         return 'black'
 
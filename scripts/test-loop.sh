@@ -44,7 +44,7 @@ set +x
 INFOLOG=$OUT_DIR/test_info.log
 echo "Test started $(date)" | tee $INFOLOG
 echo "scale $SCALE, iterations $ITERATIONS" | tee -a $INFOLOG
-for graph_type in "complete" "spanning-tree" "la-model"; do
+for graph_type in "complete" "spanning-tree" "la-model" "k-connected"; do
     if [ $graph_type = "complete" ] && [ $SCALE -gt $COMPLETE_MAX_SCALE ]; then
         echo "*** Skipping complete graph at scale $SCALE ***"
         continue
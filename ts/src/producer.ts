@@ -47,7 +47,7 @@ export class Producer {
         }
         console.debug(`--> producing message ${this.msgCount}`)
         const next_index = this.getNextIdx()
-        const rec = new PeerRecord()
+        const rec = new PeerRecord(this.msgCount + 1)
         const idOp = this.collection.findByID(this.plan.peer_doc_id)
         const recPath = `logs['${this.peerId}']['log']['${next_index}']`
         idOp.update((mutDoc: MutableDocument) => {
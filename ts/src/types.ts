@@ -26,6 +26,7 @@ export enum PeerState {
     Running,    // Executing
     Reporting,  // Finished test, outputting results
     Shutdown,   // Done, exiting
+    Offline,    // Executing, but stopped syncing as the plan's churn schedule says
 }
 
 /** @internal */
@@ -37,6 +38,16 @@ export interface Peer {
     peer_ip_addr: string;
     peer_port: number;
     state: PeerState;
+    position?: Position | null;
+    group?: string | null;
+    role?: string | null;
+    tier?: number | null;
+}
+
+/** @internal */
+export interface Position {
+    x: number;
+    y: number;
 }
 
 /** @internal */
@@ -78,15 +89,98 @@ export interface ExecutionPlan {
     max_msg_delay_msec: number
     peers: Peer[]
     connections: PeerGraph
+    graph_params: GraphParams
+    // u64 on the Rust side, so may not be exact as a number
+    seed: number
+    epochs: TopologyEpoch[]
+    partition: PartitionSchedule | null
+    churn: { [key: PeerId]: DowntimeWindow[] }
+    link_flaps: LinkFlap[]
+    mules: MuleSchedule | null
+}
+
+/** @internal */
+export interface LinkFlap {
+    dialer: PeerId
+    listener: PeerId
+    down: DowntimeWindow[]
+}
+
+/** @internal */
+export interface DowntimeWindow {
+    down_offset_msec: number
+    up_offset_msec: number
+}
+
+/** @internal */
+export interface MuleSchedule {
+    clusters: PeerId[][]
+    visits: MuleVisit[]
+}
+
+/** @internal */
+export interface MuleVisit {
+    mule: PeerId
+    cluster: number
+    arrive_offset_msec: number
+    leave_offset_msec: number
+}
+
+/** @internal */
+export interface PartitionSchedule {
+    split_offset_msec: number
+    heal_offset_msec: number
+    partitions: PeerId[][]
+}
+
+/** @internal */
+export interface TopologyEpoch {
+    offset_msec: number
+    connections: PeerGraph
+}
+
+/** @internal */
+export interface GraphParams {
+    graph_type: string
+    edge_probability: number | null
+    attach_edges: number | null
+    lattice_degree: number | null
+    rewire_probability: number | null
+    grid_rows: number | null
+    grid_cols: number | null
+    area_size: number | null
+    radio_range: number | null
+    star_hub: PeerId | null
+    clusters: number | null
+    bridges_per_cluster: number | null
+    cluster_graph: string | null
+    graph_file: string | null
+    connectivity: number | null
+    tier_fanout: number[] | null
+    gateways: number | null
+    gateway_uplinks: number | null
+    min_speed: number | null
+    max_speed: number | null
+    pause_msec: number | null
+    mobility_tick_msec: number | null
+    trace_start_sec: number | null
+    mules: number | null
+    mule_dwell_sec: number | null
+    mule_transit_sec: number | null
+    max_degree: number | null
+    orientation: string
 }
 
 /** @internal */
 export class PeerRecord {
     timestamp: number;
     data: string;
-    constructor() {
+    // Position in the producer's sequence of records, from 1
+    seq: number;
+    constructor(seq: number) {
         this.timestamp = Date.now()
         this.data = ""
+        this.seq = seq
     }
 }
 